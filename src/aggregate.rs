@@ -0,0 +1,789 @@
+//! Build a composite device out of several real ones, the same way upowerd builds its DisplayDevice.
+//!
+//! Source: `up_daemon_update_display_battery`, `up_daemon_compute_warning_level` and `up_daemon_get_charge_icon`
+//! in upowerd's `up-daemon.c`, and `update_icon_name` and `update_warning_level` in `up-device.c`
+use crate::{
+    config::UPowerConfig,
+    types::{
+        BatteryLevel, BatteryState, DeviceType, IconKind, IconLevel, IconName, IntSeconds,
+        Percentage, WarningLevel,
+    },
+    xmlgen::{DeviceDetails, DisplayDeviceDetails},
+};
+
+/// Compute the warning level for a device, with the rules and thresholds upowerd uses:
+///
+/// - A device with a coarse [`BatteryLevel`] is low if that level is low, and has no warning otherwise,
+///   except for a critical level, which goes through the rules below since it could already be time for the action.
+/// - Only discharging devices get a warning.
+/// - Mice and keyboards only report their level in big steps, so they are critical below 13% and low below 26%,
+///   to get a warning in before they run out.
+/// - Devices that power the system use the time thresholds if the config says so and the time to empty is known.
+///   Everything else uses the percentage thresholds.
+/// - A discharging UPS that isn't low yet gets [`WarningLevel::Discharging`].
+pub fn warning_level(
+    type_: DeviceType,
+    power_supply: bool,
+    state: BatteryState,
+    percentage: Percentage,
+    battery_level: BatteryLevel,
+    time_to_empty: IntSeconds,
+    config: &UPowerConfig,
+) -> WarningLevel {
+    let config = config.effective();
+
+    match battery_level {
+        BatteryLevel::None | BatteryLevel::Critical => {}
+        BatteryLevel::Low => return WarningLevel::Low,
+        _ => return WarningLevel::None,
+    }
+
+    if type_ == DeviceType::LinePower || state != BatteryState::Discharging {
+        return WarningLevel::None;
+    }
+
+    let default_level = match type_ {
+        DeviceType::Mouse | DeviceType::Keyboard => {
+            return match percentage.get() {
+                p if p < 13 => WarningLevel::Critical,
+                p if p < 26 => WarningLevel::Low,
+                _ => WarningLevel::None,
+            };
+        }
+        DeviceType::Ups => WarningLevel::Discharging,
+        _ => WarningLevel::None,
+    };
+
+    let time = time_to_empty.get();
+    if power_supply && !config.use_percentage_for_policy && !time.is_zero() {
+        if time <= config.time_action.get() {
+            WarningLevel::Action
+        } else if time <= config.time_critical.get() {
+            WarningLevel::Critical
+        } else if time <= config.time_low.get() {
+            WarningLevel::Low
+        } else {
+            default_level
+        }
+    } else if percentage <= config.percentage_action {
+        WarningLevel::Action
+    } else if percentage <= config.percentage_critical {
+        WarningLevel::Critical
+    } else if percentage <= config.percentage_low {
+        WarningLevel::Low
+    } else {
+        default_level
+    }
+}
+
+/// Pick the icon upowerd would give a device:
+///
+/// - Line power is always `ac-adapter-symbolic`
+/// - Anything that isn't present is `battery-missing-symbolic`, and so is an unknown state
+/// - Empty is `battery-empty-symbolic`, and fully charged is `battery-full-charged-symbolic`
+/// - Otherwise the level is `caution` below 10%, `low` below 30%, `good` below 60%, and `full` from there,
+///   with `-charging` added when charging. These cut-offs are fixed in upowerd and don't follow the config. Devices with a coarse [`BatteryLevel`] map critical and low to `caution`,
+///   normal to `low`, high to `good` and full to `full`.
+pub fn icon_name(
+    type_: DeviceType,
+    is_present: bool,
+    state: BatteryState,
+    percentage: Percentage,
+    battery_level: BatteryLevel,
+) -> IconName {
+    let icon = |kind, level, charging, charged| IconName {
+        kind,
+        level,
+        charging,
+        charged,
+        symbolic: true,
+    };
+
+    if type_ == DeviceType::LinePower {
+        return icon(IconKind::AcAdapter, IconLevel::None, false, false);
+    }
+    if !is_present {
+        return icon(IconKind::Battery, IconLevel::Missing, false, false);
+    }
+
+    let charging = match state {
+        BatteryState::Empty => return icon(IconKind::Battery, IconLevel::Empty, false, false),
+        BatteryState::FullyCharged => return icon(IconKind::Battery, IconLevel::Full, false, true),
+        BatteryState::Charging | BatteryState::PendingCharge => true,
+        BatteryState::Discharging | BatteryState::PendingDischarge => false,
+        BatteryState::Unknown => return icon(IconKind::Battery, IconLevel::Missing, false, false),
+    };
+
+    let level = match battery_level {
+        BatteryLevel::None => match percentage.get() {
+            p if p < 10 => IconLevel::Caution,
+            p if p < 30 => IconLevel::Low,
+            p if p < 60 => IconLevel::Good,
+            _ => IconLevel::Full,
+        },
+        // upowerd deliberately uses the non-symbolic icon here
+        BatteryLevel::Unknown => {
+            return IconName {
+                symbolic: false,
+                ..icon(IconKind::Battery, IconLevel::Missing, false, false)
+            }
+        }
+        BatteryLevel::Critical | BatteryLevel::Low => IconLevel::Caution,
+        BatteryLevel::Normal => IconLevel::Low,
+        BatteryLevel::High => IconLevel::Good,
+        BatteryLevel::Full => IconLevel::Full,
+    };
+
+    icon(IconKind::Battery, level, charging, false)
+}
+
+/// The `battery-level-N` style icon that GNOME Shell shows, in steps of 10% rounded down.
+///
+/// A full battery on external power is `battery-level-100-charged-symbolic`. Anything [`icon_name`] doesn't
+/// give a level to keeps the icon from there.
+pub fn level_icon_name(
+    type_: DeviceType,
+    is_present: bool,
+    state: BatteryState,
+    percentage: Percentage,
+    battery_level: BatteryLevel,
+) -> IconName {
+    let mut icon = icon_name(type_, is_present, state, percentage, battery_level);
+    let percentage = battery_level.approximate_percentage().unwrap_or(percentage);
+
+    match icon.level {
+        IconLevel::Caution | IconLevel::Low | IconLevel::Good => {
+            icon.level = IconLevel::Level(percentage.get() / 10 * 10)
+        }
+        IconLevel::Full => {
+            icon.level = IconLevel::Level(match icon.charged {
+                true => 100,
+                false => percentage.get() / 10 * 10,
+            })
+        }
+        _ => {}
+    }
+    if icon.level == IconLevel::Level(100) && icon.charging {
+        icon.charging = false;
+        icon.charged = true;
+    }
+
+    icon
+}
+
+/// Fill in `icon_name` the way upowerd does
+pub fn fill_icon_name(details: &mut DisplayDeviceDetails) {
+    details.icon_name = icon_name(
+        details.type_,
+        details.is_present,
+        details.state,
+        details.percentage,
+        BatteryLevel::None,
+    )
+    .to_string();
+}
+
+/// Combine the devices into one, following upowerd's DisplayDevice rules:
+///
+/// - Only batteries that power the system, and UPSes, are counted. Everything else is skipped.
+/// - If a UPS is discharging, it is used on its own, because the system is running on it.
+///   It is also used if there are no batteries.
+/// - If any battery is charging, the composite is charging. If all the others are discharging, it is discharging.
+///   If they are all fully charged, it is fully charged. Anything else is unknown.
+/// - Energies and rates are summed, and the percentage and times are computed from the sums.
+///
+/// `icon_name` is filled in with [`fill_icon_name`].
+pub fn aggregate<'a>(
+    devices: impl IntoIterator<Item = &'a DeviceDetails>,
+    config: &UPowerConfig,
+) -> DisplayDeviceDetails {
+    let counted = devices
+        .into_iter()
+        .filter(|d| match d.type_ {
+            DeviceType::Battery => d.power_supply && d.is_present,
+            DeviceType::Ups => d.is_present,
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+
+    let has_batteries = counted.iter().any(|d| d.type_ == DeviceType::Battery);
+    let ups = counted.iter().find(|d| {
+        d.type_ == DeviceType::Ups && (d.state == BatteryState::Discharging || !has_batteries)
+    });
+
+    if let Some(ups) = ups {
+        let mut me = DisplayDeviceDetails::from(*ups);
+        me.warning_level = warning_level(
+            me.type_,
+            ups.power_supply,
+            me.state,
+            me.percentage,
+            ups.battery_level,
+            me.time_to_empty,
+            config,
+        );
+        fill_icon_name(&mut me);
+        return me;
+    }
+
+    let mut me = DisplayDeviceDetails::default();
+    let mut energy_full = 0.0;
+
+    for device in counted.iter().filter(|d| d.type_ == DeviceType::Battery) {
+        me.state = match (device.state, me.state) {
+            (BatteryState::Charging, _) => BatteryState::Charging,
+            (BatteryState::Discharging, total) if total != BatteryState::Charging => {
+                BatteryState::Discharging
+            }
+            (BatteryState::FullyCharged, BatteryState::Unknown) => BatteryState::FullyCharged,
+            (_, total) => total,
+        };
+
+        me.type_ = DeviceType::Battery;
+        me.is_present = true;
+        me.energy += device.energy;
+        energy_full += device.energy_full;
+        me.energy_rate += device.energy_rate;
+    }
+    me.energy_full = energy_full;
+
+    if !me.is_present {
+        fill_icon_name(&mut me);
+        return me;
+    }
+
+    if energy_full > 0.0 {
+        me.percentage =
+            Percentage::from_f64(100.0 * me.energy / energy_full).unwrap_or(Percentage::MAX);
+    }
+
+    if me.energy_rate > 0.0 {
+        let hours = match me.state {
+            BatteryState::Discharging => Some(me.energy / me.energy_rate),
+            BatteryState::Charging => Some((energy_full - me.energy) / me.energy_rate),
+            _ => None,
+        };
+        if let Some(hours) = hours {
+            let seconds = IntSeconds::new_from_unsigned((hours.max(0.0) * 3600.0) as u64);
+            match me.state {
+                BatteryState::Discharging => me.time_to_empty = seconds,
+                _ => me.time_to_full = seconds,
+            }
+        }
+    }
+
+    me.warning_level = warning_level(
+        me.type_,
+        true,
+        me.state,
+        me.percentage,
+        BatteryLevel::None,
+        me.time_to_empty,
+        config,
+    );
+    fill_icon_name(&mut me);
+
+    me
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn percent(p: u8) -> Percentage {
+        Percentage::new_saturating(p)
+    }
+
+    fn minutes(m: u64) -> IntSeconds {
+        IntSeconds::new_from_unsigned(m * 60)
+    }
+
+    #[test]
+    fn warning_levels_by_percentage() {
+        let config = UPowerConfig::default();
+        let level = |type_, power_supply, state, p| {
+            warning_level(
+                type_,
+                power_supply,
+                state,
+                percent(p),
+                BatteryLevel::None,
+                IntSeconds::default(),
+                &config,
+            )
+        };
+        use {BatteryState as S, DeviceType as K, WarningLevel as W};
+
+        for (type_, power_supply, state, percentage, expected) in [
+            (K::Battery, true, S::Discharging, 50, W::None),
+            (K::Battery, true, S::Discharging, 21, W::None),
+            (K::Battery, true, S::Discharging, 20, W::Low),
+            (K::Battery, true, S::Discharging, 5, W::Critical),
+            (K::Battery, true, S::Discharging, 3, W::Critical),
+            (K::Battery, true, S::Discharging, 2, W::Action),
+            (K::Battery, true, S::Charging, 2, W::None),
+            (K::Battery, true, S::PendingCharge, 2, W::None),
+            (K::Ups, true, S::Discharging, 50, W::Discharging),
+            (K::Ups, true, S::Discharging, 10, W::Low),
+            (K::Ups, true, S::FullyCharged, 100, W::None),
+            (K::LinePower, true, S::Unknown, 0, W::None),
+            // Peripherals
+            (K::Mouse, false, S::Discharging, 30, W::None),
+            (K::Mouse, false, S::Discharging, 25, W::Low),
+            (K::Keyboard, false, S::Discharging, 13, W::Low),
+            (K::Keyboard, false, S::Discharging, 12, W::Critical),
+            (K::Keyboard, false, S::Discharging, 1, W::Critical),
+            (K::Mouse, false, S::Charging, 1, W::None),
+            (K::Headset, false, S::Discharging, 20, W::Low),
+            (K::Headset, false, S::Discharging, 2, W::Action),
+            (K::Phone, false, S::Discharging, 40, W::None),
+        ] {
+            assert_eq!(
+                level(type_, power_supply, state, percentage),
+                expected,
+                "{type_:?} {power_supply} {state:?} {percentage}%"
+            );
+        }
+    }
+
+    #[test]
+    fn warning_levels_by_time() {
+        let config = UPowerConfig {
+            use_percentage_for_policy: false,
+            ..Default::default()
+        };
+        let level = |power_supply, p, time| {
+            warning_level(
+                DeviceType::Battery,
+                power_supply,
+                BatteryState::Discharging,
+                percent(p),
+                BatteryLevel::None,
+                time,
+                &config,
+            )
+        };
+
+        assert_eq!(level(true, 10, minutes(60)), WarningLevel::None);
+        assert_eq!(level(true, 50, minutes(20)), WarningLevel::Low);
+        assert_eq!(level(true, 50, minutes(5)), WarningLevel::Critical);
+        assert_eq!(level(true, 50, minutes(2)), WarningLevel::Action);
+        // Without a time, the percentage is used
+        assert_eq!(level(true, 10, IntSeconds::default()), WarningLevel::Low);
+        assert_eq!(level(true, 50, IntSeconds::default()), WarningLevel::None);
+        // Devices that don't power the system always use the percentage
+        assert_eq!(level(false, 50, minutes(2)), WarningLevel::None);
+        assert_eq!(level(false, 4, minutes(60)), WarningLevel::Critical);
+    }
+
+    #[test]
+    fn warning_levels_by_coarse_level() {
+        let config = UPowerConfig::default();
+        let level = |battery_level, p| {
+            warning_level(
+                DeviceType::Mouse,
+                false,
+                BatteryState::Discharging,
+                percent(p),
+                battery_level,
+                IntSeconds::default(),
+                &config,
+            )
+        };
+
+        assert_eq!(level(BatteryLevel::Full, 100), WarningLevel::None);
+        assert_eq!(level(BatteryLevel::High, 70), WarningLevel::None);
+        assert_eq!(level(BatteryLevel::Normal, 55), WarningLevel::None);
+        assert_eq!(level(BatteryLevel::Low, 10), WarningLevel::Low);
+        assert_eq!(level(BatteryLevel::Unknown, 0), WarningLevel::None);
+        // Critical falls through to the percentage rules
+        assert_eq!(level(BatteryLevel::Critical, 5), WarningLevel::Critical);
+        assert_eq!(level(BatteryLevel::Critical, 20), WarningLevel::Low);
+    }
+
+    #[test]
+    fn icon_names() {
+        use {BatteryLevel as L, BatteryState as S, DeviceType as K};
+        let name = |type_, is_present, state, p, battery_level| {
+            icon_name(type_, is_present, state, percent(p), battery_level).to_string()
+        };
+
+        for (type_, is_present, state, percentage, battery_level, expected) in [
+            (
+                K::LinePower,
+                true,
+                S::Unknown,
+                0,
+                L::None,
+                "ac-adapter-symbolic",
+            ),
+            (
+                K::Battery,
+                false,
+                S::Discharging,
+                50,
+                L::None,
+                "battery-missing-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::Unknown,
+                50,
+                L::None,
+                "battery-missing-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::Empty,
+                0,
+                L::None,
+                "battery-empty-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::FullyCharged,
+                100,
+                L::None,
+                "battery-full-charged-symbolic",
+            ),
+            // The fixed percentage cut-offs
+            (
+                K::Battery,
+                true,
+                S::Discharging,
+                0,
+                L::None,
+                "battery-caution-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::Discharging,
+                9,
+                L::None,
+                "battery-caution-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::Discharging,
+                10,
+                L::None,
+                "battery-low-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::Discharging,
+                29,
+                L::None,
+                "battery-low-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::Discharging,
+                30,
+                L::None,
+                "battery-good-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::PendingDischarge,
+                59,
+                L::None,
+                "battery-good-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::Discharging,
+                60,
+                L::None,
+                "battery-full-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::Charging,
+                5,
+                L::None,
+                "battery-caution-charging-symbolic",
+            ),
+            (
+                K::Battery,
+                true,
+                S::PendingCharge,
+                80,
+                L::None,
+                "battery-full-charging-symbolic",
+            ),
+            // Coarse levels ignore the percentage
+            (
+                K::Mouse,
+                true,
+                S::Discharging,
+                0,
+                L::Unknown,
+                "battery-missing",
+            ),
+            (
+                K::Mouse,
+                true,
+                S::Discharging,
+                90,
+                L::Critical,
+                "battery-caution-symbolic",
+            ),
+            (
+                K::Mouse,
+                true,
+                S::Discharging,
+                90,
+                L::Low,
+                "battery-caution-symbolic",
+            ),
+            (
+                K::Mouse,
+                true,
+                S::Discharging,
+                0,
+                L::Normal,
+                "battery-low-symbolic",
+            ),
+            (
+                K::Mouse,
+                true,
+                S::Discharging,
+                0,
+                L::High,
+                "battery-good-symbolic",
+            ),
+            (
+                K::Mouse,
+                true,
+                S::Charging,
+                0,
+                L::Full,
+                "battery-full-charging-symbolic",
+            ),
+        ] {
+            assert_eq!(
+                name(type_, is_present, state, percentage, battery_level),
+                expected,
+                "{type_:?} {is_present} {state:?} {percentage}% {battery_level:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn level_icon_names() {
+        use {BatteryLevel as L, BatteryState as S, DeviceType as K};
+        let name = |state, p, battery_level| {
+            level_icon_name(K::Battery, true, state, percent(p), battery_level).to_string()
+        };
+
+        for (state, percentage, battery_level, expected) in [
+            (S::Discharging, 0, L::None, "battery-level-0-symbolic"),
+            (S::Discharging, 9, L::None, "battery-level-0-symbolic"),
+            (S::Discharging, 10, L::None, "battery-level-10-symbolic"),
+            (S::Discharging, 95, L::None, "battery-level-90-symbolic"),
+            (S::Discharging, 100, L::None, "battery-level-100-symbolic"),
+            (S::Charging, 0, L::None, "battery-level-0-charging-symbolic"),
+            (S::Charging, 9, L::None, "battery-level-0-charging-symbolic"),
+            (
+                S::Charging,
+                10,
+                L::None,
+                "battery-level-10-charging-symbolic",
+            ),
+            (
+                S::PendingCharge,
+                95,
+                L::None,
+                "battery-level-90-charging-symbolic",
+            ),
+            // A full battery that is still "charging" is shown as charged
+            (
+                S::Charging,
+                100,
+                L::None,
+                "battery-level-100-charged-symbolic",
+            ),
+            (
+                S::FullyCharged,
+                97,
+                L::None,
+                "battery-level-100-charged-symbolic",
+            ),
+            // Icons without a level are left alone
+            (S::Empty, 0, L::None, "battery-empty-symbolic"),
+            (S::Unknown, 50, L::None, "battery-missing-symbolic"),
+            // Coarse levels use their approximate percentage
+            (S::Discharging, 90, L::Critical, "battery-level-0-symbolic"),
+            (S::Discharging, 90, L::Low, "battery-level-10-symbolic"),
+            (S::Discharging, 0, L::Normal, "battery-level-50-symbolic"),
+            (S::Discharging, 0, L::High, "battery-level-80-symbolic"),
+            (
+                S::Charging,
+                0,
+                L::Full,
+                "battery-level-100-charged-symbolic",
+            ),
+            (S::Discharging, 0, L::Unknown, "battery-missing"),
+        ] {
+            assert_eq!(
+                name(state, percentage, battery_level),
+                expected,
+                "{state:?} {percentage}% {battery_level:?}"
+            );
+        }
+        assert_eq!(
+            level_icon_name(K::LinePower, true, S::Unknown, percent(0), L::None).to_string(),
+            "ac-adapter-symbolic"
+        );
+    }
+
+    fn device(
+        type_: DeviceType,
+        state: BatteryState,
+        energy: f64,
+        energy_full: f64,
+        energy_rate: f64,
+    ) -> DeviceDetails {
+        DeviceDetails {
+            type_,
+            state,
+            energy,
+            energy_full,
+            energy_rate,
+            percentage: Percentage::from_f64(100.0 * energy / energy_full).unwrap_or_default(),
+            battery_level: BatteryLevel::None,
+            power_supply: true,
+            is_present: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sums_batteries() {
+        let config = UPowerConfig::default();
+        use {BatteryState as S, DeviceType as K};
+
+        let total = aggregate(
+            &[
+                device(K::Battery, S::Discharging, 30.0, 50.0, 12.0),
+                device(K::Battery, S::Discharging, 10.0, 30.0, 8.0),
+            ],
+            &config,
+        );
+        assert_eq!((total.type_, total.is_present), (K::Battery, true));
+        assert_eq!(total.state, S::Discharging);
+        assert_eq!((total.energy, total.energy_full), (40.0, 80.0));
+        assert_eq!(total.energy_rate, 20.0);
+        // Not the average of 60% and 33%
+        assert_eq!(total.percentage, percent(50));
+        assert_eq!(total.time_to_empty, minutes(120));
+        assert_eq!(total.time_to_full, IntSeconds::default());
+        assert_eq!(total.warning_level, WarningLevel::None);
+        assert_eq!(total.icon_name, "battery-good-symbolic");
+
+        let total = aggregate(
+            &[
+                device(K::Battery, S::Charging, 30.0, 50.0, 15.0),
+                device(K::Battery, S::Charging, 10.0, 30.0, 5.0),
+            ],
+            &config,
+        );
+        assert_eq!(total.time_to_full, minutes(120));
+        assert_eq!(total.time_to_empty, IntSeconds::default());
+
+        let total = aggregate(
+            &[
+                device(K::Battery, S::Discharging, 5.0, 50.0, 10.0),
+                device(K::Battery, S::Discharging, 3.0, 30.0, 10.0),
+            ],
+            &config,
+        );
+        assert_eq!(total.percentage, percent(10));
+        assert_eq!(total.warning_level, WarningLevel::Low);
+    }
+
+    #[test]
+    fn combines_states() {
+        let config = UPowerConfig::default();
+        use BatteryState as S;
+
+        for (states, expected) in [
+            ([S::Charging, S::Discharging], S::Charging),
+            ([S::Discharging, S::Charging], S::Charging),
+            ([S::FullyCharged, S::Discharging], S::Discharging),
+            ([S::Discharging, S::FullyCharged], S::Discharging),
+            ([S::FullyCharged, S::FullyCharged], S::FullyCharged),
+            ([S::FullyCharged, S::Charging], S::Charging),
+            ([S::PendingCharge, S::Unknown], S::Unknown),
+        ] {
+            let devices = states.map(|state| device(DeviceType::Battery, state, 20.0, 40.0, 0.0));
+            assert_eq!(aggregate(&devices, &config).state, expected, "{states:?}");
+        }
+    }
+
+    #[test]
+    fn discharging_ups_wins() {
+        let config = UPowerConfig::default();
+        use {BatteryState as S, DeviceType as K};
+
+        let battery = device(K::Battery, S::Charging, 20.0, 40.0, 10.0);
+        let ups = device(K::Ups, S::Discharging, 80.0, 100.0, 40.0);
+        let total = aggregate([&battery, &ups], &config);
+        assert_eq!(total.type_, K::Ups);
+        assert_eq!(total.state, S::Discharging);
+        assert_eq!(total.percentage, percent(80));
+        assert_eq!(total.warning_level, WarningLevel::Discharging);
+
+        // A UPS on mains power only counts when there's no battery
+        let ups = device(K::Ups, S::FullyCharged, 100.0, 100.0, 0.0);
+        let total = aggregate([&battery, &ups], &config);
+        assert_eq!((total.type_, total.state), (K::Battery, S::Charging));
+        assert_eq!(total.percentage, percent(50));
+
+        let total = aggregate([&ups], &config);
+        assert_eq!((total.type_, total.state), (K::Ups, S::FullyCharged));
+    }
+
+    #[test]
+    fn only_counts_power_supplies() {
+        let config = UPowerConfig::default();
+        use {BatteryState as S, DeviceType as K};
+
+        let battery = device(K::Battery, S::Discharging, 20.0, 40.0, 10.0);
+        let ignored = [
+            // A battery inside a peripheral
+            DeviceDetails {
+                power_supply: false,
+                ..device(K::Battery, S::Discharging, 1.0, 10.0, 1.0)
+            },
+            // An empty battery bay
+            DeviceDetails {
+                is_present: false,
+                ..device(K::Battery, S::Discharging, 1.0, 10.0, 1.0)
+            },
+            device(K::Mouse, S::Discharging, 1.0, 10.0, 1.0),
+            device(K::LinePower, S::Unknown, 0.0, 0.0, 0.0),
+        ];
+
+        let total = aggregate(ignored.iter().chain([&battery]), &config);
+        assert_eq!((total.energy, total.energy_full), (20.0, 40.0));
+        assert_eq!(total.energy_rate, 10.0);
+        assert_eq!(total.percentage, percent(50));
+
+        let total = aggregate(&ignored, &config);
+        assert!(!total.is_present);
+        assert_eq!(total.type_, K::Unknown);
+        assert_eq!(total.energy_full, 0.0);
+    }
+}
@@ -0,0 +1,31 @@
+//! Keyboard backlight control, on top of the `KbdBacklight` proxy.
+use crate::{logging::*, xmlgen::keyboard::KbdBacklightProxy};
+
+/// Step the brightness up by one, wrapping around to off after the brightest level. Returns the new brightness.
+pub async fn cycle(proxy: &KbdBacklightProxy<'_>) -> ::zbus::Result<i32> {
+    let (current, max) = ::futures_util::join!(proxy.get_brightness(), proxy.get_max_brightness());
+    let (current, max) = (current?, max?);
+
+    let next = match current >= max {
+        true => 0,
+        false => current + 1,
+    };
+    debug!("Cycling keyboard backlight from {} to {}", current, next);
+
+    proxy.set_brightness(next).await?;
+    Ok(next)
+}
+
+/// Step the brightness up or down by `delta`, stopping at off and the brightest level. Returns the new brightness.
+pub async fn step(proxy: &KbdBacklightProxy<'_>, delta: i32) -> ::zbus::Result<i32> {
+    let (current, max) = ::futures_util::join!(proxy.get_brightness(), proxy.get_max_brightness());
+    let (current, max) = (current?, max?);
+
+    let next = current.saturating_add(delta).clamp(0, max.max(0));
+    debug!("Stepping keyboard backlight from {} to {}", current, next);
+
+    if next != current {
+        proxy.set_brightness(next).await?;
+    }
+    Ok(next)
+}
@@ -0,0 +1,161 @@
+//! Status lines for polybar and lemonbar, with click actions that call back into the `upowerz` command.
+//!
+//! Both bars understand the same tags. For polybar, use a script module with `tail = true` running `upowerz bar`.
+//! For lemonbar, pipe `upowerz bar` into lemonbar, and lemonbar's output into `sh`, since lemonbar prints the
+//! click actions instead of running them.
+use {
+    crate::{
+        style::{Markup, Palette, Styler},
+        types::IconSet,
+        xmlgen::{display_device::DeviceProxy, DisplayDeviceDetails},
+    },
+    ::futures_util::{Stream, StreamExt},
+    ::std::borrow::Cow,
+};
+
+/// The mouse buttons, numbered the way the `%{A}` tag wants them
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Button {
+    Left = 1,
+    Middle = 2,
+    Right = 3,
+    ScrollUp = 4,
+    ScrollDown = 5,
+}
+
+/// Commands to run when the module is clicked. Unset buttons do nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClickActions {
+    pub actions: Vec<(Button, String)>,
+}
+impl ClickActions {
+    /// Toggle the charge limit on left click, and cycle the keyboard backlight on right click, using `command` to run upowerz.
+    pub fn new(command: &str) -> Self {
+        Self {
+            actions: vec![
+                (Button::Left, format!("{command} charge-limit toggle")),
+                (Button::Right, format!("{command} kbd-backlight cycle")),
+            ],
+        }
+    }
+
+    /// Wrap text in all of the action tags
+    pub fn wrap(&self, text: &str) -> String {
+        let mut wrapped = String::new();
+        for (button, command) in self.actions.iter() {
+            wrapped.push_str(&format!(
+                "%{{A{}:{}:}}",
+                *button as u8,
+                escape_command(command)
+            ));
+        }
+        wrapped.push_str(text);
+        for _ in self.actions.iter() {
+            wrapped.push_str("%{A}");
+        }
+        wrapped
+    }
+}
+impl Default for ClickActions {
+    /// Call back into the running executable, so it works without `upowerz` on `PATH`
+    fn default() -> Self {
+        match ::std::env::current_exe() {
+            Ok(path) => Self::new(&shell_quote(&path.to_string_lossy())),
+            Err(_) => Self::new("upowerz"),
+        }
+    }
+}
+
+/// Colons end the command in an action tag, so they have to be escaped
+fn escape_command(command: &str) -> String {
+    command.replace(':', "\\:")
+}
+
+/// Both bars run their actions with `sh`, so paths with spaces and the like need quoting
+fn shell_quote(word: &str) -> Cow<'_, str> {
+    let plain = |c: char| c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '-' | '_' | '+');
+    match !word.is_empty() && word.chars().all(plain) {
+        true => Cow::Borrowed(word),
+        false => Cow::Owned(format!("'{}'", word.replace('\'', r"'\''"))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BarOutput {
+    pub palette: Palette,
+    pub icons: IconSet,
+    pub actions: ClickActions,
+}
+impl BarOutput {
+    /// Format a single line, without the trailing newline
+    pub fn line(&self, details: &DisplayDeviceDetails) -> String {
+        let styler = Styler::new(Markup::Polybar, self.palette);
+        self.actions.wrap(&styler.battery(details, &self.icons))
+    }
+
+    /// Yield a new line every time the display device changes, starting with the current state.
+    pub async fn watch<'a>(
+        &'a self,
+        proxy: &'a DeviceProxy<'a>,
+    ) -> impl Stream<Item = String> + 'a {
+        DisplayDeviceDetails::watch(proxy)
+            .await
+            .map(move |details| self.line(&details))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_in_every_action() {
+        let actions = ClickActions::new("upowerz");
+        assert_eq!(
+            actions.wrap("text"),
+            "%{A1:upowerz charge-limit toggle:}%{A3:upowerz kbd-backlight cycle:}text%{A}%{A}"
+        );
+
+        let actions = ClickActions {
+            actions: vec![
+                (Button::ScrollUp, "up".to_owned()),
+                (Button::ScrollDown, "down".to_owned()),
+                (Button::Middle, "middle".to_owned()),
+            ],
+        };
+        assert_eq!(
+            actions.wrap("text"),
+            "%{A4:up:}%{A5:down:}%{A2:middle:}text%{A}%{A}%{A}"
+        );
+        assert_eq!(ClickActions { actions: vec![] }.wrap("text"), "text");
+    }
+
+    #[test]
+    fn escapes_commands() {
+        assert_eq!(escape_command("upowerz"), "upowerz");
+        assert_eq!(escape_command("notify-send a:b:c"), r"notify-send a\:b\:c");
+        assert_eq!(
+            ClickActions::new("/opt/a:b/upowerz").wrap("x"),
+            r"%{A1:/opt/a\:b/upowerz charge-limit toggle:}%{A3:/opt/a\:b/upowerz kbd-backlight cycle:}x%{A}%{A}"
+        );
+    }
+
+    #[test]
+    fn quotes_paths() {
+        assert_eq!(shell_quote("/usr/bin/upowerz"), "/usr/bin/upowerz");
+        assert_eq!(
+            shell_quote("/home/me/my bin/upowerz"),
+            "'/home/me/my bin/upowerz'"
+        );
+        assert_eq!(shell_quote("/tmp/it's"), r"'/tmp/it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn defaults_to_this_executable() {
+        let exe = ::std::env::current_exe().unwrap();
+        let expected = ClickActions::new(&shell_quote(&exe.to_string_lossy()));
+        assert_eq!(ClickActions::default(), expected);
+    }
+}
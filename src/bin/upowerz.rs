@@ -0,0 +1,337 @@
+//! A small command line tool on top of the library, mostly for status bars to call.
+use {
+    ::futures_util::StreamExt,
+    ::std::{
+        io::{self, Read, Write},
+        path::PathBuf,
+        process::{Command, ExitCode, Stdio},
+    },
+    ::tokio::signal::unix::SignalKind,
+    ::upowerz::{
+        backlight,
+        bar::{BarOutput, ClickActions},
+        broker,
+        cache::{self, StatusCache},
+        charge_limit::ChargeLimiter,
+        dashboard::{self, Action, Change, Dashboard},
+        device::PowerDeviceProxy,
+        display_device::DeviceProxy,
+        keyboard::KbdBacklightProxy,
+        types::DeviceType,
+        upower::UPowerProxy,
+    },
+};
+
+const USAGE: &str = "\
+Usage: upowerz <command>
+
+Commands:
+  bar [--command CMD]    Print a polybar/lemonbar line every time the battery changes.
+                         Clicks call back into CMD, which defaults to this executable.
+  broker [SOCKET]        Share one set of UPower subscriptions with local clients
+  cache watch [DIR]      Keep a status cache up to date, for `cache read`
+  cache read [--json] [DIR]
+                         Print the cached status without touching D-Bus.
+                         Exits with 2 if the watcher isn't running anymore.
+  charge-limit toggle    Turn the battery charge limit on or off
+  dashboard              Show every power device in a full-screen, live view
+  kbd-backlight cycle    Step the keyboard backlight up, wrapping around to off";
+
+type Error = Box<dyn std::error::Error>;
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    // This has to stay fast, so it doesn't start a runtime
+    if let ["cache", "read", rest @ ..] = args.as_slice() {
+        return cache_read(rest);
+    }
+
+    let runtime = match ::tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Failed to start the async runtime: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = runtime.block_on(async {
+        match args.as_slice() {
+            ["bar"] => bar(ClickActions::default()).await,
+            ["bar", "--command", command] => bar(ClickActions::new(command)).await,
+            ["broker"] => serve_broker(broker::default_socket_path()).await,
+            ["broker", socket] => serve_broker(PathBuf::from(socket)).await,
+            ["cache", "watch"] => cache_watch(cache::default_dir()).await,
+            ["cache", "watch", dir] => cache_watch(PathBuf::from(dir)).await,
+            ["charge-limit", "toggle"] => charge_limit_toggle().await,
+            ["dashboard"] => dashboard().await,
+            ["kbd-backlight", "cycle"] => kbd_backlight_cycle().await,
+            ["-h" | "--help" | "help"] => {
+                println!("{USAGE}");
+                Ok(())
+            }
+            _ => Err(USAGE.into()),
+        }
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn bar(actions: ClickActions) -> Result<(), Error> {
+    let connection = ::zbus::Connection::system().await?;
+    let proxy = DeviceProxy::new(&connection).await?;
+    let output = BarOutput {
+        actions,
+        ..Default::default()
+    };
+
+    let lines = output.watch(&proxy).await;
+    let mut lines = ::core::pin::pin!(lines);
+    let mut stdout = std::io::stdout();
+    while let Some(line) = lines.next().await {
+        writeln!(stdout, "{line}")?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+async fn serve_broker(socket: PathBuf) -> Result<(), Error> {
+    let connection = ::zbus::Connection::system().await?;
+    broker::serve(connection, &socket).await?;
+    Ok(())
+}
+
+async fn cache_watch(dir: PathBuf) -> Result<(), Error> {
+    // This can block for a moment, which is fine while nothing else is running yet
+    let watcher = StatusCache::new(dir).start()?;
+    let connection = ::zbus::Connection::system().await?;
+    let proxy = DeviceProxy::new(&connection).await?;
+    watcher.watch(&proxy).await?;
+    Ok(())
+}
+
+fn cache_read(args: &[&str]) -> ExitCode {
+    let (json, dir) = match args {
+        [] => (false, cache::default_dir()),
+        ["--json"] => (true, cache::default_dir()),
+        [dir] => (false, PathBuf::from(dir)),
+        ["--json", dir] => (true, PathBuf::from(dir)),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let status = match StatusCache::read(&dir) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match json {
+        true => match ::serde_json::to_string(&status) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        false => println!("{}", status.text),
+    }
+
+    match StatusCache::is_stale(&dir) {
+        true => {
+            eprintln!("The status cache is stale, the watcher isn't running anymore");
+            ExitCode::from(2)
+        }
+        false => ExitCode::SUCCESS,
+    }
+}
+
+async fn charge_limit_toggle() -> Result<(), Error> {
+    let connection = ::zbus::Connection::system().await?;
+    let upower = UPowerProxy::new(&connection).await?;
+
+    for path in upower.enumerate_devices().await? {
+        let proxy = PowerDeviceProxy::new(&connection, path).await?;
+        if proxy.type_().await? != DeviceType::Battery || !proxy.power_supply().await? {
+            continue;
+        }
+
+        let limiter = ChargeLimiter::new(proxy);
+        if limiter.is_supported().await? {
+            let enabled = limiter.toggle().await?;
+            println!(
+                "Charge limit {}",
+                match enabled {
+                    true => "enabled",
+                    false => "disabled",
+                }
+            );
+            return Ok(());
+        }
+    }
+
+    Err("No battery supports charge limits".into())
+}
+
+/// Something the dashboard has to redraw for
+enum DashboardEvent {
+    /// Bytes read from stdin, which can hold more than one key
+    Input(Vec<u8>),
+    /// A change on D-Bus
+    Change(Change),
+    /// The terminal was resized
+    Resize,
+}
+
+async fn dashboard() -> Result<(), Error> {
+    let connection = ::zbus::Connection::system().await?;
+    let changes = dashboard::watch(connection.clone()).await?;
+    let mut dashboard = Dashboard::default();
+    dashboard.refresh(&connection).await?;
+
+    let terminal = RawTerminal::enter()?;
+    let (sender, mut events) = ::tokio::sync::mpsc::channel(16);
+
+    // Neither the thread nor the task is joined. They end when the channel closes, or with the process.
+    let keys = sender.clone();
+    std::thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut buffer = [0; 16];
+        while let Ok(n @ 1..) = stdin.read(&mut buffer) {
+            if keys
+                .blocking_send(DashboardEvent::Input(buffer[..n].to_vec()))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+    let mut resizes = ::tokio::signal::unix::signal(SignalKind::window_change())?;
+    let resized = sender.clone();
+    ::tokio::spawn(async move {
+        while resizes.recv().await.is_some() {
+            if resized.send(DashboardEvent::Resize).await.is_err() {
+                break;
+            }
+        }
+    });
+    ::tokio::spawn(async move {
+        let mut changes = ::core::pin::pin!(changes);
+        while let Some(change) = changes.next().await {
+            if sender.send(DashboardEvent::Change(change)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    terminal.draw(&dashboard)?;
+    while let Some(event) = events.recv().await {
+        match event {
+            DashboardEvent::Input(input) => {
+                for action in Action::from_input(&input) {
+                    if !dashboard.apply(&connection, action).await {
+                        return Ok(());
+                    }
+                }
+            }
+            DashboardEvent::Change(change) => dashboard.update(&connection, change).await,
+            DashboardEvent::Resize => {}
+        }
+        terminal.draw(&dashboard)?;
+    }
+
+    Ok(())
+}
+
+/// Puts the terminal in raw mode on the alternate screen, and puts it back the way it was when dropped.
+///
+/// The mode is switched with `stty`, so it works on anything with a POSIX shell without pulling in a terminal library.
+struct RawTerminal {
+    /// The settings from before, as printed by `stty -g`
+    saved: String,
+}
+impl RawTerminal {
+    fn enter() -> Result<Self, Error> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+
+        // Alternate screen, hidden cursor
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        stdout.flush()?;
+
+        Ok(Self {
+            saved: saved.trim().to_owned(),
+        })
+    }
+
+    /// The terminal size as `(columns, rows)`, or 80×24 if it can't be found
+    fn size() -> (usize, usize) {
+        let mut size = ::libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: TIOCGWINSZ only writes a winsize to the pointer it's given
+        match unsafe { ::libc::ioctl(::libc::STDOUT_FILENO, ::libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_col > 0 && size.ws_row > 0 => (size.ws_col as usize, size.ws_row as usize),
+            _ => (80, 24),
+        }
+    }
+
+    fn draw(&self, dashboard: &Dashboard) -> io::Result<()> {
+        let (width, height) = Self::size();
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(dashboard.render(width, height).as_bytes())?;
+        stdout.flush()
+    }
+}
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+        let _ = stty(&[self.saved.as_str()]);
+    }
+}
+
+/// Run `stty` on the terminal this is running in, and return what it printed
+fn stty(args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Err(format!(
+            "stty {} failed, is this running in a terminal?",
+            args.join(" ")
+        )
+        .into()),
+    }
+}
+
+async fn kbd_backlight_cycle() -> Result<(), Error> {
+    let connection = ::zbus::Connection::system().await?;
+    let proxy = KbdBacklightProxy::new(&connection).await?;
+    let brightness = backlight::cycle(&proxy).await?;
+    println!("Keyboard backlight set to {brightness}");
+    Ok(())
+}
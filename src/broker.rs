@@ -0,0 +1,592 @@
+//! Share one set of UPower subscriptions between many local clients.
+//!
+//! The broker listens on a Unix socket, and speaks newline-delimited JSON. A client sends one [`Request`] line,
+//! then gets a [`Message`] line for everything it asked for. Subscribers get the current state first, so they don't
+//! have to wait for the next change.
+//!
+//! The client here is blocking and needs no runtime. The broker itself needs the `tokio` feature.
+use {
+    crate::{peripherals::PeripheralEvent, warning::WarningEvent, xmlgen::DisplayDeviceDetails},
+    ::core::fmt,
+    ::serde::{Deserialize, Serialize},
+    ::std::{
+        io::{self, BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+        path::{Path, PathBuf},
+    },
+};
+
+/// `broker.sock` in the same directory as the status cache, see [`crate::cache::default_dir`]
+pub fn default_socket_path() -> PathBuf {
+    crate::cache::default_dir().join("broker.sock")
+}
+
+/// What a client can ask for
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    strum_macros::Display,
+    strum_macros::AsRefStr,
+    strum_macros::EnumString,
+    strum_macros::EnumIter,
+    Serialize,
+    Deserialize,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum Topic {
+    /// Every change to the display device
+    Display,
+    /// Warning level transitions, from a [`crate::warning::WarningTracker`] with the default hysteresis
+    Warnings,
+    /// Peripherals being added, changing, and being removed
+    Peripherals,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum Request {
+    /// Send the current state of these topics, then keep sending updates
+    Subscribe { topics: Vec<Topic> },
+    /// Send the current state of these topics, then close the connection
+    Snapshot { topics: Vec<Topic> },
+}
+impl Request {
+    pub fn topics(&self) -> &[Topic] {
+        match self {
+            Self::Subscribe { topics } | Self::Snapshot { topics } => topics,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Message {
+    Display(DisplayDeviceDetails),
+    Warning(WarningEvent),
+    Peripheral(PeripheralEvent),
+    /// The request couldn't be understood, or the broker is shutting down
+    Error(String),
+}
+impl Message {
+    /// The topic this message belongs to, if any
+    pub const fn topic(&self) -> Option<Topic> {
+        match self {
+            Self::Display(_) => Some(Topic::Display),
+            Self::Warning(_) => Some(Topic::Warnings),
+            Self::Peripheral(_) => Some(Topic::Peripherals),
+            Self::Error(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BrokerError {
+    Io(io::Error),
+    Json(::serde_json::Error),
+    /// The broker sent back an error message
+    Broker(String),
+}
+impl fmt::Display for BrokerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Broker connection failed: {e}"),
+            Self::Json(e) => write!(f, "Invalid broker message: {e}"),
+            Self::Broker(e) => write!(f, "Broker error: {e}"),
+        }
+    }
+}
+impl std::error::Error for BrokerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Broker(_) => None,
+        }
+    }
+}
+impl From<io::Error> for BrokerError {
+    #[inline]
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+impl From<::serde_json::Error> for BrokerError {
+    #[inline]
+    fn from(e: ::serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// A connection to the broker. Iterate over it to get messages; it ends when the broker closes the connection.
+#[derive(Debug)]
+pub struct BrokerClient {
+    reader: BufReader<UnixStream>,
+    line: String,
+}
+impl BrokerClient {
+    fn send(path: &Path, request: &Request) -> Result<Self, BrokerError> {
+        let mut stream = UnixStream::connect(path)?;
+        let mut line = ::serde_json::to_string(request)?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+
+        Ok(Self {
+            reader: BufReader::new(stream),
+            line: String::new(),
+        })
+    }
+
+    /// Subscribe to some topics. The current state of each one comes first.
+    pub fn subscribe(path: &Path, topics: &[Topic]) -> Result<Self, BrokerError> {
+        Self::send(
+            path,
+            &Request::Subscribe {
+                topics: topics.to_vec(),
+            },
+        )
+    }
+
+    /// Get the current state of some topics, without subscribing
+    pub fn snapshot(path: &Path, topics: &[Topic]) -> Result<Vec<Message>, BrokerError> {
+        Self::send(
+            path,
+            &Request::Snapshot {
+                topics: topics.to_vec(),
+            },
+        )?
+        .collect()
+    }
+
+    /// Wait for the next message. Returns `None` once the broker closes the connection.
+    pub fn next_message(&mut self) -> Result<Option<Message>, BrokerError> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line)? == 0 {
+            return Ok(None);
+        }
+
+        match ::serde_json::from_str(&self.line)? {
+            Message::Error(e) => Err(BrokerError::Broker(e)),
+            message => Ok(Some(message)),
+        }
+    }
+}
+impl Iterator for BrokerClient {
+    type Item = Result<Message, BrokerError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_message().transpose()
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use server::serve;
+
+#[cfg(feature = "tokio")]
+mod server {
+    use {
+        super::{Message, Request, Topic},
+        crate::{
+            logging::*,
+            peripherals::{self, PeripheralEvent},
+            warning::WarningTracker,
+            xmlgen::{display_device::DeviceProxy, upower::UPowerProxy, DisplayDeviceDetails},
+        },
+        ::futures_util::{
+            future::{select, Either},
+            StreamExt,
+        },
+        ::std::{
+            collections::HashMap,
+            io,
+            path::Path,
+            sync::{Arc, Mutex},
+        },
+        ::tokio::{
+            io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+            net::{UnixListener, UnixStream},
+            sync::broadcast,
+            task::JoinSet,
+        },
+        ::zbus::zvariant::OwnedObjectPath,
+    };
+
+    /// How many messages a slow client can fall behind before it starts missing some
+    const BACKLOG: usize = 64;
+
+    /// The latest state of every topic, for new clients
+    #[derive(Default)]
+    struct State {
+        display: Option<DisplayDeviceDetails>,
+        warning: Option<Message>,
+        peripherals: HashMap<OwnedObjectPath, PeripheralEvent>,
+    }
+    impl State {
+        fn snapshot(&self, topics: &[Topic]) -> Vec<Message> {
+            let mut messages = Vec::new();
+            if topics.contains(&Topic::Display) {
+                messages.extend(self.display.clone().map(Message::Display));
+            }
+            if topics.contains(&Topic::Warnings) {
+                messages.extend(self.warning.clone());
+            }
+            if topics.contains(&Topic::Peripherals) {
+                messages.extend(self.peripherals.values().cloned().map(Message::Peripheral));
+            }
+            messages
+        }
+    }
+
+    /// Run the broker on a socket until something fails. A stale socket file at the path is replaced.
+    ///
+    /// The socket's directory is created like the status cache's, and has to pass the same checks.
+    /// If UPower can't be watched anymore, subscribers get a [`Message::Error`] and this returns the same error.
+    ///
+    /// This has to run inside a tokio runtime.
+    pub async fn serve(connection: ::zbus::Connection, socket_path: &Path) -> io::Result<()> {
+        if let Some(dir) = socket_path.parent() {
+            crate::cache::create_private_dir(dir)?;
+        }
+        // Only remove it if nothing is listening on it
+        if ::std::os::unix::net::UnixStream::connect(socket_path).is_err() {
+            let _ = ::std::fs::remove_file(socket_path);
+        }
+        let listener = UnixListener::bind(socket_path)?;
+        info!("Broker listening on {}", socket_path.display());
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let (sender, _) = broadcast::channel::<Message>(BACKLOG);
+
+        // Dropping these when returning stops whichever watcher is still running
+        let mut watchers = JoinSet::new();
+        watchers.spawn(watch_display(
+            connection.clone(),
+            state.clone(),
+            sender.clone(),
+        ));
+        watchers.spawn(watch_peripherals(connection, state.clone(), sender.clone()));
+
+        let accepting = ::core::pin::pin!(accept_clients(&listener, &state, &sender));
+        let watching = ::core::pin::pin!(watchers.join_next());
+        let reason = match select(accepting, watching).await {
+            Either::Left((result, _)) => return result,
+            Either::Right((Some(Ok(reason)), _)) => reason,
+            Either::Right((Some(Err(e)), _)) => format!("A broker watcher failed: {e}"),
+            Either::Right((None, _)) => "Nothing left to watch".to_owned(),
+        };
+
+        warning!("{}", reason);
+        // Clients see this before the channel closes, since it's the last message sent
+        let _ = sender.send(Message::Error(reason.clone()));
+        Err(io::Error::other(reason))
+    }
+
+    async fn accept_clients(
+        listener: &UnixListener,
+        state: &Arc<Mutex<State>>,
+        sender: &broadcast::Sender<Message>,
+    ) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            // Subscribe before the snapshot is taken, so nothing falls in between
+            let receiver = sender.subscribe();
+            ::tokio::spawn(handle_client(stream, state.clone(), receiver));
+        }
+    }
+
+    /// Keep the display device state up to date. Returns why it stopped.
+    async fn watch_display(
+        connection: ::zbus::Connection,
+        state: Arc<Mutex<State>>,
+        sender: broadcast::Sender<Message>,
+    ) -> String {
+        let proxy = match DeviceProxy::new(&connection).await {
+            Ok(p) => p,
+            Err(e) => return format!("Broker failed to watch the display device: {e}"),
+        };
+        let mut tracker = WarningTracker::default();
+
+        let updates = DisplayDeviceDetails::watch(&proxy).await;
+        let mut updates = ::core::pin::pin!(updates);
+        while let Some(details) = updates.next().await {
+            let warning = tracker.update(&details).map(Message::Warning);
+            {
+                let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+                state.display = Some(details.clone());
+                if warning.is_some() {
+                    state.warning = warning.clone();
+                }
+            }
+
+            // Sending only fails when there are no clients, which is fine
+            let _ = sender.send(Message::Display(details));
+            if let Some(warning) = warning {
+                let _ = sender.send(warning);
+            }
+        }
+        "The display device stopped sending updates".to_owned()
+    }
+
+    /// Keep the peripheral states up to date. Returns why it stopped.
+    async fn watch_peripherals(
+        connection: ::zbus::Connection,
+        state: Arc<Mutex<State>>,
+        sender: broadcast::Sender<Message>,
+    ) -> String {
+        let upower = match UPowerProxy::new(&connection).await {
+            Ok(p) => p,
+            Err(e) => return format!("Broker failed to watch peripherals: {e}"),
+        };
+        let events = match peripherals::watch_all(connection, upower, Vec::new()).await {
+            Ok(e) => e,
+            Err(e) => return format!("Broker failed to watch peripherals: {e}"),
+        };
+
+        let mut events = ::core::pin::pin!(events);
+        while let Some(event) = events.next().await {
+            {
+                let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+                match &event {
+                    PeripheralEvent::Added(p) | PeripheralEvent::Changed(p) => {
+                        // New clients should see it as added, no matter how many times it changed since
+                        state
+                            .peripherals
+                            .insert(p.path.clone(), PeripheralEvent::Added(p.clone()));
+                    }
+                    PeripheralEvent::Removed(path) => {
+                        state.peripherals.remove(path);
+                    }
+                }
+            }
+            let _ = sender.send(Message::Peripheral(event));
+        }
+        "Peripherals stopped sending updates".to_owned()
+    }
+
+    async fn handle_client(
+        stream: UnixStream,
+        state: Arc<Mutex<State>>,
+        mut receiver: broadcast::Receiver<Message>,
+    ) {
+        let (reader, mut writer) = stream.into_split();
+        let mut line = String::new();
+
+        let request = match BufReader::new(reader).read_line(&mut line).await {
+            Ok(0) => return,
+            Ok(_) => ::serde_json::from_str::<Request>(&line),
+            Err(e) => {
+                debug!("Failed to read a broker request: {}", e);
+                return;
+            }
+        };
+        let request = match request {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = send(&mut writer, &Message::Error(e.to_string())).await;
+                return;
+            }
+        };
+        let topics = request.topics().to_vec();
+
+        let snapshot = state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .snapshot(&topics);
+        for message in snapshot.iter() {
+            if send(&mut writer, message).await.is_err() {
+                return;
+            }
+        }
+        if let Request::Snapshot { .. } = request {
+            return;
+        }
+
+        loop {
+            let message = match receiver.recv().await {
+                Ok(m) => m,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warning!("A broker client fell behind and missed {} messages", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+
+            // Errors go to everyone
+            if message.topic().is_none_or(|t| topics.contains(&t))
+                && send(&mut writer, &message).await.is_err()
+            {
+                trace!("Broker client disconnected");
+                return;
+            }
+        }
+    }
+
+    async fn send(
+        writer: &mut ::tokio::net::unix::OwnedWriteHalf,
+        message: &Message,
+    ) -> io::Result<()> {
+        let mut line = ::serde_json::to_string(message)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use {
+            super::*,
+            crate::{
+                peripherals::Peripheral,
+                types::{BatteryLevel, DeviceType, Percentage, WarningLevel},
+                warning::{WarningEvent, WarningTransition},
+            },
+        };
+
+        fn peripheral(path: &str) -> PeripheralEvent {
+            PeripheralEvent::Added(Peripheral {
+                path: OwnedObjectPath::try_from(path).unwrap(),
+                type_: DeviceType::Mouse,
+                vendor: String::new(),
+                model: String::new(),
+                serial: String::new(),
+                percentage: Percentage::new_saturating(50),
+                battery_level: BatteryLevel::None,
+                state: Default::default(),
+                warning_level: Default::default(),
+                is_present: true,
+                icon_name: String::new(),
+            })
+        }
+
+        #[test]
+        fn snapshots_only_have_the_requested_topics() {
+            let mut state = State::default();
+            assert!(state.snapshot(&[Topic::Display]).is_empty());
+
+            state.display = Some(DisplayDeviceDetails::default());
+            state.warning = Some(Message::Warning(WarningEvent {
+                transition: WarningTransition::Escalated,
+                from: WarningLevel::None,
+                to: WarningLevel::Low,
+                details: DisplayDeviceDetails::default(),
+            }));
+            for path in ["/mouse", "/keyboard"] {
+                state
+                    .peripherals
+                    .insert(OwnedObjectPath::try_from(path).unwrap(), peripheral(path));
+            }
+
+            let topics = |topics: &[Topic]| {
+                state
+                    .snapshot(topics)
+                    .into_iter()
+                    .map(|m| m.topic())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(topics(&[]), vec![]);
+            assert_eq!(topics(&[Topic::Display]), vec![Some(Topic::Display)]);
+            assert_eq!(topics(&[Topic::Warnings]), vec![Some(Topic::Warnings)]);
+            assert_eq!(
+                topics(&[Topic::Peripherals]),
+                vec![Some(Topic::Peripherals); 2]
+            );
+            assert_eq!(
+                topics(&[Topic::Peripherals, Topic::Display]).len(),
+                3,
+                "Every requested topic is included once"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            peripherals::Peripheral,
+            types::{BatteryLevel, BatteryState, DeviceType, Percentage, WarningLevel},
+            warning::{WarningEvent, WarningTransition},
+        },
+        ::zbus::zvariant::OwnedObjectPath,
+    };
+
+    #[test]
+    fn requests_round_trip() {
+        for (request, json) in [
+            (
+                Request::Subscribe {
+                    topics: vec![Topic::Display, Topic::Warnings],
+                },
+                r#"{"request":"subscribe","topics":["display","warnings"]}"#,
+            ),
+            (
+                Request::Snapshot {
+                    topics: vec![Topic::Peripherals],
+                },
+                r#"{"request":"snapshot","topics":["peripherals"]}"#,
+            ),
+        ] {
+            assert_eq!(::serde_json::to_string(&request).unwrap(), json);
+            assert_eq!(::serde_json::from_str::<Request>(json).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let details = DisplayDeviceDetails {
+            percentage: Percentage::new_saturating(9),
+            state: BatteryState::Discharging,
+            warning_level: WarningLevel::Low,
+            ..Default::default()
+        };
+        let mouse = Peripheral {
+            path: OwnedObjectPath::try_from("/org/freedesktop/UPower/devices/mouse_0").unwrap(),
+            type_: DeviceType::Mouse,
+            vendor: "Logitech".to_owned(),
+            model: "MX Master 3".to_owned(),
+            serial: String::new(),
+            percentage: Percentage::new_saturating(5),
+            battery_level: BatteryLevel::None,
+            state: BatteryState::Discharging,
+            warning_level: WarningLevel::Low,
+            is_present: true,
+            icon_name: String::new(),
+        };
+
+        for (message, tag) in [
+            (Message::Display(details.clone()), "display"),
+            (
+                Message::Warning(WarningEvent {
+                    transition: WarningTransition::Escalated,
+                    from: WarningLevel::None,
+                    to: WarningLevel::Low,
+                    details,
+                }),
+                "warning",
+            ),
+            (
+                Message::Peripheral(PeripheralEvent::Added(mouse.clone())),
+                "peripheral",
+            ),
+            (
+                Message::Peripheral(PeripheralEvent::Changed(mouse.clone())),
+                "peripheral",
+            ),
+            (
+                Message::Peripheral(PeripheralEvent::Removed(mouse.path)),
+                "peripheral",
+            ),
+            (Message::Error("Nothing left to watch".to_owned()), "error"),
+        ] {
+            let json = ::serde_json::to_string(&message).unwrap();
+            assert!(json.starts_with(&format!(r#"{{"{tag}":"#)), "{json}");
+
+            let parsed = ::serde_json::from_str::<Message>(&json).unwrap();
+            assert_eq!(parsed.topic(), message.topic(), "{json}");
+            assert_eq!(::serde_json::to_string(&parsed).unwrap(), json);
+        }
+    }
+}
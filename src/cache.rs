@@ -0,0 +1,411 @@
+//! A status file for things that check the battery very often, like tmux status lines and shell prompts.
+//!
+//! A watcher subscribes to the display device and rewrites the cache every time it changes, and readers only read a file.
+//! The files are replaced atomically, so a reader never sees half of one.
+//!
+//! The directory holds state that other programs trust, so it has to belong to the user: see [`create_private_dir`].
+use {
+    crate::{
+        logging::*,
+        style::Styler,
+        types::{BatteryState, IconSet, Percentage, WarningLevel},
+        xmlgen::{display_device::DeviceProxy, DisplayDeviceDetails},
+    },
+    ::core::fmt,
+    ::futures_util::StreamExt,
+    ::serde::{Deserialize, Serialize},
+    ::std::{
+        fs, io,
+        os::unix::fs::{DirBuilderExt, MetadataExt},
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    },
+};
+
+/// The plain text status, like `󰁾 55%`
+pub const TEXT_FILE: &str = "status.txt";
+/// The full [`CachedStatus`]
+pub const JSON_FILE: &str = "status.json";
+/// Locked by the [`CacheWatcher`] for as long as it runs. See [`StatusCache::is_stale`].
+pub const LOCK_FILE: &str = "watcher.lock";
+
+/// `$XDG_RUNTIME_DIR/upowerz`, or `upowerz-<uid>` in the system's temporary directory if that isn't set
+pub fn default_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("upowerz"),
+        None => std::env::temp_dir().join(format!("upowerz-{}", current_uid())),
+    }
+}
+
+/// Create `dir` and any missing parents with mode 0700, then check it with [`check_private_dir`].
+///
+/// The temporary directory is shared, so someone else may have made the directory first.
+pub fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    check_private_dir(dir)
+}
+
+/// Make sure `dir` is a real directory (not a symlink), owned by this user, and not writable by anyone else.
+pub fn check_private_dir(dir: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(dir)?;
+    let problem = match () {
+        _ if !metadata.is_dir() => "is not a directory",
+        _ if metadata.uid() != current_uid() => "belongs to another user",
+        _ if metadata.mode() & 0o022 != 0 => "is writable by other users",
+        _ => return Ok(()),
+    };
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{} {problem}", dir.display()),
+    ))
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and can't fail
+    unsafe { ::libc::geteuid() }
+}
+
+/// Serialize enums as the names upowerd uses, so the JSON is readable from scripts
+mod by_name {
+    use {
+        crate::types::UPowerName,
+        ::serde::{de::Error, Deserialize, Deserializer, Serializer},
+    };
+
+    pub fn serialize<T: UPowerName, S: Serializer>(value: &T, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(value.upower_name())
+    }
+
+    pub fn deserialize<'de, T: UPowerName, D: Deserializer<'de>>(d: D) -> Result<T, D::Error> {
+        let name = String::deserialize(d)?;
+        T::from_upower_name(&name).ok_or_else(|| D::Error::custom(format!("unknown name {name}")))
+    }
+}
+
+/// What gets written to the JSON file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedStatus {
+    /// Seconds since the Unix epoch
+    pub written_at: u64,
+    /// The process ID of the watcher that wrote this. Use [`StatusCache::is_stale`] to check whether it's still running.
+    pub pid: u32,
+    /// The same as the text file
+    pub text: String,
+    pub is_present: bool,
+    pub percentage: Percentage,
+    #[serde(with = "by_name")]
+    pub state: BatteryState,
+    #[serde(with = "by_name")]
+    pub warning_level: WarningLevel,
+    /// In seconds, 0 if unknown
+    pub time_to_empty: i64,
+    /// In seconds, 0 if unknown
+    pub time_to_full: i64,
+    /// In Wh
+    pub energy: f64,
+    /// In W
+    pub energy_rate: f64,
+    pub icon_name: String,
+}
+impl CachedStatus {
+    pub fn new(details: &DisplayDeviceDetails, text: String) -> Self {
+        Self {
+            written_at: unix_time(SystemTime::now()),
+            pid: std::process::id(),
+            text,
+            is_present: details.is_present,
+            percentage: details.percentage,
+            state: details.state,
+            warning_level: details.warning_level,
+            time_to_empty: details.time_to_empty.as_signed_secs(),
+            time_to_full: details.time_to_full.as_signed_secs(),
+            energy: details.energy,
+            energy_rate: details.energy_rate,
+            icon_name: details.icon_name.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(PathBuf, io::Error),
+    Json(::serde_json::Error),
+    /// Another watcher is already keeping this directory up to date
+    AlreadyWatched(PathBuf),
+}
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "Failed to access {}: {}", path.display(), e),
+            Self::Json(e) => write!(f, "Invalid status cache: {e}"),
+            Self::AlreadyWatched(dir) => {
+                write!(f, "Another watcher is already running in {}", dir.display())
+            }
+        }
+    }
+}
+impl std::error::Error for CacheError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(_, e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::AlreadyWatched(_) => None,
+        }
+    }
+}
+impl From<::serde_json::Error> for CacheError {
+    #[inline]
+    fn from(e: ::serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// The cache directory, and how to fill it in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusCache {
+    pub dir: PathBuf,
+    pub styler: Styler,
+    pub icons: IconSet,
+}
+impl StatusCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            styler: Styler::default(),
+            icons: IconSet::default(),
+        }
+    }
+
+    /// Replace both files with the new status
+    pub fn write(&self, details: &DisplayDeviceDetails) -> Result<(), CacheError> {
+        create_private_dir(&self.dir).map_err(|e| CacheError::Io(self.dir.clone(), e))?;
+
+        let text = self.styler.battery(details, &self.icons);
+        let status = CachedStatus::new(details, text);
+
+        self.replace(JSON_FILE, ::serde_json::to_string(&status)?.as_bytes())?;
+        self.replace(TEXT_FILE, format!("{}\n", status.text).as_bytes())
+    }
+
+    /// Write to a temporary file next to the real one, then rename it over, so readers never see a partial file
+    fn replace(&self, name: &str, contents: &[u8]) -> Result<(), CacheError> {
+        let path = self.dir.join(name);
+        let temporary = self.dir.join(format!(".{name}.{}", std::process::id()));
+
+        fs::write(&temporary, contents)
+            .and_then(|()| fs::rename(&temporary, &path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&temporary);
+                CacheError::Io(path, e)
+            })
+    }
+
+    /// Become the watcher for this directory, by taking the lock on [`LOCK_FILE`]. Fails if another watcher already has it.
+    ///
+    /// This blocks for a moment if a reader is checking the lock, so call it before starting any async work.
+    pub fn start(self) -> Result<CacheWatcher, CacheError> {
+        create_private_dir(&self.dir).map_err(|e| CacheError::Io(self.dir.clone(), e))?;
+        let lock = self.lock()?;
+        Ok(CacheWatcher {
+            cache: self,
+            _lock: lock,
+        })
+    }
+
+    /// Take the watcher lock. The OS releases it when the file is closed, including when the process dies.
+    fn lock(&self) -> Result<fs::File, CacheError> {
+        let path = self.dir.join(LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| CacheError::Io(path.clone(), e))?;
+
+        // A reader in `is_stale` holds a shared lock for a moment, so don't give up on the first try
+        for _ in 0..10 {
+            match file.try_lock() {
+                Ok(()) => return Ok(file),
+                Err(fs::TryLockError::WouldBlock) => std::thread::sleep(Duration::from_millis(10)),
+                Err(fs::TryLockError::Error(e)) => return Err(CacheError::Io(path, e)),
+            }
+        }
+        Err(CacheError::AlreadyWatched(self.dir.clone()))
+    }
+
+    /// Whether nothing is keeping the cache in `dir` up to date anymore.
+    ///
+    /// The cache is only written when something changes, so its age says nothing. Instead this checks
+    /// whether the watcher's lock is still held. If the lock can't be checked at all, the watcher is assumed to be running.
+    pub fn is_stale(dir: &Path) -> bool {
+        let file = match fs::File::open(dir.join(LOCK_FILE)) {
+            Ok(f) => f,
+            Err(_) => return true,
+        };
+        match file.try_lock_shared() {
+            Ok(()) => true,
+            Err(fs::TryLockError::WouldBlock) => false,
+            Err(fs::TryLockError::Error(e)) => {
+                debug!("Failed to check the status cache lock: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Read the full status, without touching D-Bus. `dir` has to pass [`check_private_dir`].
+    pub fn read(dir: &Path) -> Result<CachedStatus, CacheError> {
+        check_private_dir(dir).map_err(|e| CacheError::Io(dir.to_owned(), e))?;
+        let path = dir.join(JSON_FILE);
+        let json = fs::read_to_string(&path).map_err(|e| CacheError::Io(path, e))?;
+        Ok(::serde_json::from_str(&json)?)
+    }
+}
+
+/// A [`StatusCache`] that holds the watcher lock, from [`StatusCache::start`]. Dropping it releases the lock.
+#[derive(Debug)]
+pub struct CacheWatcher {
+    cache: StatusCache,
+    /// Only kept open, the lock goes with it
+    _lock: fs::File,
+}
+impl CacheWatcher {
+    #[inline]
+    pub const fn cache(&self) -> &StatusCache {
+        &self.cache
+    }
+
+    /// Keep the cache up to date until the display device goes away. Write errors are logged, and don't stop the watcher.
+    pub async fn watch(&self, proxy: &DeviceProxy<'_>) -> Result<(), CacheError> {
+        let updates = DisplayDeviceDetails::watch(proxy).await;
+        let mut updates = ::core::pin::pin!(updates);
+
+        while let Some(details) = updates.next().await {
+            match self.cache.write(&details) {
+                Ok(()) => trace!("Updated status cache in {}", self.cache.dir.display()),
+                Err(e) => warning!("Failed to update status cache: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::types::IntSeconds, ::std::os::unix::fs::PermissionsExt};
+
+    /// A fresh directory that doesn't exist yet
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("upowerz-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn private_dirs_are_created_for_this_user_only() {
+        let dir = scratch("private");
+        create_private_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+        // Creating it again is fine
+        create_private_dir(&dir).unwrap();
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        assert_eq!(
+            create_private_dir(&dir).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symlinks_are_rejected() {
+        let target = scratch("target");
+        let link = scratch("link");
+        create_private_dir(&target).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        assert_eq!(
+            create_private_dir(&link).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(StatusCache::read(&link).is_err());
+
+        fs::remove_file(&link).unwrap();
+        fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn stale_without_a_watcher() {
+        let dir = scratch("stale");
+        assert!(StatusCache::is_stale(&dir));
+
+        let watcher = StatusCache::new(dir.clone()).start().unwrap();
+        assert!(!StatusCache::is_stale(&dir));
+        assert!(matches!(
+            StatusCache::new(dir.clone()).start(),
+            Err(CacheError::AlreadyWatched(_))
+        ));
+
+        drop(watcher);
+        assert!(StatusCache::is_stale(&dir));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn status_round_trips() {
+        let dir = scratch("round-trip");
+        let cache = StatusCache::new(dir.clone());
+        let details = DisplayDeviceDetails {
+            is_present: true,
+            percentage: Percentage::new_saturating(9),
+            state: BatteryState::PendingDischarge,
+            warning_level: WarningLevel::Critical,
+            time_to_empty: IntSeconds::new_from_unsigned(900),
+            energy: 4.5,
+            energy_rate: 18.0,
+            icon_name: "battery-caution-symbolic".to_owned(),
+            ..Default::default()
+        };
+        cache.write(&details).unwrap();
+
+        let json = fs::read_to_string(dir.join(JSON_FILE)).unwrap();
+        assert!(json.contains(r#""state":"pending-discharge""#), "{json}");
+        assert!(json.contains(r#""warning_level":"critical""#), "{json}");
+
+        let status = StatusCache::read(&dir).unwrap();
+        assert_eq!(
+            status,
+            CachedStatus {
+                written_at: status.written_at,
+                ..CachedStatus::new(&details, status.text.clone())
+            }
+        );
+        assert_eq!(status.state, BatteryState::PendingDischarge);
+        assert_eq!(status.warning_level, WarningLevel::Critical);
+        assert_eq!(status.time_to_empty, 900);
+        assert_eq!(
+            fs::read_to_string(dir.join(TEXT_FILE)).unwrap(),
+            format!("{}\n", status.text)
+        );
+
+        // Enums are read back by name, not by number
+        let renamed = json.replace(r#""state":"pending-discharge""#, r#""state":"charging""#);
+        let status = ::serde_json::from_str::<CachedStatus>(&renamed).unwrap();
+        assert_eq!(status.state, BatteryState::Charging);
+        let numbered = json.replace(r#""state":"pending-discharge""#, r#""state":6"#);
+        assert!(::serde_json::from_str::<CachedStatus>(&numbered).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+//! Battery conservation mode, through UPower's charge threshold support (UPower 1.90+).
+//!
+//! The thresholds themselves are decided by the system (the kernel driver or UPower's hwdb),
+//! so all a client can do is check them and turn them on or off.
+use {
+    crate::{logging::*, types::Percentage, xmlgen::device::PowerDeviceProxy},
+    ::core::fmt,
+    ::serde::{Deserialize, Serialize},
+};
+
+/// The charge limits of a single battery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChargeLimits {
+    /// The battery won't start charging until it drops below this
+    pub start: Percentage,
+    /// The battery stops charging when it reaches this
+    pub end: Percentage,
+    /// Whether the limits are currently being applied
+    pub enabled: bool,
+}
+impl fmt::Display for ChargeLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{} ({})",
+            self.start,
+            self.end,
+            match self.enabled {
+                true => "enabled",
+                false => "disabled",
+            }
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ChargeLimitError {
+    /// The UPower daemon is too old to know about charge thresholds
+    DaemonUnsupported,
+    /// The daemon knows about charge thresholds, but this battery doesn't support them
+    HardwareUnsupported,
+    Dbus(::zbus::Error),
+}
+impl From<::zbus::Error> for ChargeLimitError {
+    fn from(value: ::zbus::Error) -> Self {
+        match value {
+            ::zbus::Error::FDO(ref e)
+                if matches!(
+                    **e,
+                    ::zbus::fdo::Error::UnknownProperty(_)
+                        | ::zbus::fdo::Error::UnknownMethod(_)
+                        | ::zbus::fdo::Error::InvalidArgs(_)
+                ) =>
+            {
+                Self::DaemonUnsupported
+            }
+            ::zbus::Error::MethodError(ref name, _, _)
+                if name.as_str() == "org.freedesktop.DBus.Error.UnknownMethod" =>
+            {
+                Self::DaemonUnsupported
+            }
+            e => Self::Dbus(e),
+        }
+    }
+}
+impl fmt::Display for ChargeLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DaemonUnsupported => {
+                f.write_str("This version of UPower does not support charge thresholds")
+            }
+            Self::HardwareUnsupported => {
+                f.write_str("This battery does not support charge thresholds")
+            }
+            Self::Dbus(e) => write!(f, "D-Bus error: {e}"),
+        }
+    }
+}
+impl std::error::Error for ChargeLimitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Dbus(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Controls the charge limits of a single battery.
+#[derive(Debug, Clone)]
+pub struct ChargeLimiter<'c> {
+    proxy: PowerDeviceProxy<'c>,
+}
+impl<'c> ChargeLimiter<'c> {
+    pub const fn new(proxy: PowerDeviceProxy<'c>) -> Self {
+        Self { proxy }
+    }
+
+    #[inline]
+    pub const fn proxy(&self) -> &PowerDeviceProxy<'c> {
+        &self.proxy
+    }
+
+    /// Check if this battery supports charge limits at all.
+    ///
+    /// This returns `Ok(false)` rather than an error if the daemon is too old.
+    pub async fn is_supported(&self) -> Result<bool, ChargeLimitError> {
+        match self.proxy.charge_threshold_supported().await {
+            Ok(supported) => Ok(supported),
+            Err(e) => match ChargeLimitError::from(e) {
+                ChargeLimitError::DaemonUnsupported => Ok(false),
+                e => Err(e),
+            },
+        }
+    }
+
+    async fn ensure_supported(&self) -> Result<(), ChargeLimitError> {
+        match self.proxy.charge_threshold_supported().await? {
+            true => Ok(()),
+            false => Err(ChargeLimitError::HardwareUnsupported),
+        }
+    }
+
+    /// Get the current charge limits
+    pub async fn limits(&self) -> Result<ChargeLimits, ChargeLimitError> {
+        self.ensure_supported().await?;
+
+        let (start, end, enabled) = ::futures_util::join!(
+            self.proxy.charge_start_threshold(),
+            self.proxy.charge_end_threshold(),
+            self.proxy.charge_threshold_enabled(),
+        );
+
+        Ok(ChargeLimits {
+            start: start?,
+            end: end?,
+            enabled: enabled?,
+        })
+    }
+
+    /// Turn conservation mode on or off
+    pub async fn set_enabled(&self, enabled: bool) -> Result<(), ChargeLimitError> {
+        self.ensure_supported().await?;
+
+        debug!(
+            "Setting charge threshold on {} to {}",
+            self.proxy.inner().path(),
+            enabled
+        );
+        self.proxy.enable_charge_threshold(enabled).await?;
+        Ok(())
+    }
+
+    /// Flip conservation mode, returning whether it is now enabled
+    pub async fn toggle(&self) -> Result<bool, ChargeLimitError> {
+        let enabled = !self.limits().await?.enabled;
+        self.set_enabled(enabled).await?;
+        Ok(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, ::zbus::fdo};
+
+    /// An error reply to a call of `EnableChargeThreshold`
+    fn method_error(name: &str, description: Option<&str>) -> ::zbus::Error {
+        let call = ::zbus::Message::method_call("/org/freedesktop/UPower", "EnableChargeThreshold")
+            .unwrap()
+            .build(&())
+            .unwrap();
+        ::zbus::Error::MethodError(
+            name.try_into().unwrap(),
+            description.map(str::to_owned),
+            call,
+        )
+    }
+
+    #[test]
+    fn old_daemons_are_unsupported() {
+        for error in [
+            fdo::Error::UnknownProperty("ChargeThresholdSupported".to_owned()),
+            fdo::Error::UnknownMethod("EnableChargeThreshold".to_owned()),
+            fdo::Error::InvalidArgs("No such property".to_owned()),
+        ] {
+            let name = format!("{error:?}");
+            assert!(
+                matches!(
+                    ChargeLimitError::from(::zbus::Error::FDO(Box::new(error))),
+                    ChargeLimitError::DaemonUnsupported
+                ),
+                "{name}"
+            );
+        }
+
+        // Unknown methods can also come back as a plain method error
+        assert!(matches!(
+            ChargeLimitError::from(method_error(
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                None
+            )),
+            ChargeLimitError::DaemonUnsupported
+        ));
+    }
+
+    #[test]
+    fn other_errors_are_kept() {
+        for error in [
+            ::zbus::Error::FDO(Box::new(fdo::Error::AccessDenied("Not allowed".to_owned()))),
+            ::zbus::Error::FDO(Box::new(fdo::Error::ServiceUnknown("No UPower".to_owned()))),
+            ::zbus::Error::InterfaceNotFound,
+            method_error(
+                "org.freedesktop.UPower.Device.Failed",
+                Some("The kernel refused"),
+            ),
+        ] {
+            let name = format!("{error:?}");
+            assert!(
+                matches!(ChargeLimitError::from(error), ChargeLimitError::Dbus(_)),
+                "{name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! Text charts of battery history, for terminals and status bars.
+use crate::{history::HistoryItem, types::BatteryState};
+
+/// How a history chart is laid out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChartStyle {
+    /// In characters, not counting the axis labels
+    pub width: usize,
+    /// In lines, for the charts that take more than one
+    pub height: usize,
+    /// The bottom of the value axis. Values below it are clamped.
+    pub min: f64,
+    /// The top of the value axis. Values above it are clamped.
+    pub max: f64,
+    /// Printed after the values on the value axis, like `%` or ` W`
+    pub unit: &'static str,
+    /// Whether to add a value axis on the left and a time axis underneath
+    pub labels: bool,
+    /// Whether to add a line under the chart that marks the state, `+` for charging and `-` for discharging
+    pub state_markers: bool,
+}
+impl Default for ChartStyle {
+    fn default() -> Self {
+        Self {
+            width: 60,
+            height: 8,
+            min: 0.0,
+            max: 100.0,
+            unit: "%",
+            labels: true,
+            state_markers: true,
+        }
+    }
+}
+impl ChartStyle {
+    /// Where a value sits between `min` and `max`, from 0 to 1
+    fn level(&self, value: f64) -> f64 {
+        let range = self.max - self.min;
+        match range > 0.0 {
+            true => ((value - self.min) / range).clamp(0.0, 1.0),
+            false => 0.0,
+        }
+    }
+
+    fn label(&self, value: f64) -> String {
+        format!("{}{}", (value * 10.0).round() / 10.0, self.unit)
+    }
+
+    /// Put the value axis labels on the chart rows, then add the marker and time axis rows
+    fn finish(
+        &self,
+        rows: Vec<String>,
+        columns: &[Option<(f64, BatteryState)>],
+        points: &[HistoryItem],
+    ) -> String {
+        let (mut top, bottom) = (self.label(self.max), self.label(self.min));
+        // A single row gets the whole range
+        if rows.len() == 1 {
+            top = format!("{}-{top}", (self.min * 10.0).round() / 10.0);
+        }
+        let label_width = match self.labels {
+            true => top.chars().count().max(bottom.chars().count()) + 1,
+            false => 0,
+        };
+        let last_row = rows.len().saturating_sub(1);
+
+        let mut lines = rows
+            .into_iter()
+            .enumerate()
+            .map(|(i, row)| match (self.labels, i) {
+                (false, _) => row,
+                (true, 0) => format!("{top:>w$}│{row}", w = label_width - 1),
+                (true, i) if i == last_row => format!("{bottom:>w$}│{row}", w = label_width - 1),
+                (true, _) => format!("{:>label_width$}{row}", "│"),
+            })
+            .collect::<Vec<_>>();
+
+        if self.state_markers {
+            let markers = columns
+                .iter()
+                .map(|c| c.map_or(' ', |(_, state)| state.chart_marker()))
+                .collect::<String>();
+            lines.push(format!("{:label_width$}{markers}", ""));
+        }
+
+        if self.labels {
+            if let (Some(first), Some(last)) = (points.first(), points.last()) {
+                let start = format!("-{}", short_duration(last.time.saturating_sub(first.time)));
+                let end = "0";
+                // Leave the time axis out rather than run the labels together
+                if let Some(gap) = self
+                    .width
+                    .checked_sub(start.chars().count() + end.len())
+                    .filter(|gap| *gap > 0)
+                {
+                    lines.push(format!("{:label_width$}{start}{:gap$}{end}", "", ""));
+                }
+            }
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Like `2h 5m`, for time axis labels
+pub(crate) fn short_duration(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{seconds}s"),
+        (0, 0, m) => format!("{m}m"),
+        (0, h, m) => format!("{h}h {m}m"),
+        (d, h, _) => format!("{d}d {h}h"),
+    }
+}
+
+/// Split the time the points span into `columns` equal slices, and average the points in each one.
+///
+/// Each slice takes the state of the last point in it. Slices without points repeat the one before,
+/// so gaps in the history don't break up the chart.
+fn resample(points: &[HistoryItem], columns: usize) -> Vec<Option<(f64, BatteryState)>> {
+    let mut sums = vec![None::<(f64, usize, BatteryState)>; columns];
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return vec![None; columns];
+    };
+    let span = last.time.saturating_sub(first.time) + 1;
+
+    for point in points {
+        let offset = point.time.saturating_sub(first.time);
+        let column = ((offset as u128 * columns as u128 / span as u128) as usize).min(columns - 1);
+        let slot = sums[column].get_or_insert((0.0, 0, point.state));
+        slot.0 += point.value;
+        slot.1 += 1;
+        slot.2 = point.state;
+    }
+
+    let mut previous = None;
+    sums.into_iter()
+        .map(|slot| {
+            if let Some((sum, count, state)) = slot {
+                previous = Some((sum / count as f64, state));
+            }
+            previous
+        })
+        .collect()
+}
+
+const SPARK_BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// From empty to full, in eighths
+const BAR_BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Draw history as a one-line sparkline, like `▂▃▅▇█▇▅▃`.
+pub fn sparkline(points: &[HistoryItem], style: &ChartStyle) -> String {
+    if style.width == 0 {
+        return String::new();
+    }
+    let columns = resample(points, style.width);
+    let row = columns
+        .iter()
+        .map(|c| match c {
+            Some((value, _)) => SPARK_BLOCKS[(style.level(*value) * 7.0).round() as usize],
+            None => ' ',
+        })
+        .collect::<String>();
+
+    style.finish(vec![row], &columns, points)
+}
+
+/// Draw history as vertical bars made of block characters, `height` lines tall.
+pub fn block_bars(points: &[HistoryItem], style: &ChartStyle) -> String {
+    if style.width == 0 || style.height == 0 {
+        return String::new();
+    }
+    let columns = resample(points, style.width);
+    // In eighths of a line
+    let heights = columns
+        .iter()
+        .map(|c| {
+            c.map_or(0, |(value, _)| {
+                (style.level(value) * (style.height * 8) as f64).round() as usize
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let rows = (0..style.height)
+        .rev()
+        .map(|row| {
+            heights
+                .iter()
+                .map(|h| BAR_BLOCKS[h.saturating_sub(row * 8).min(8)])
+                .collect::<String>()
+        })
+        .collect();
+
+    style.finish(rows, &columns, points)
+}
+
+/// Draw history as a line made of braille dots. Every character holds 2×4 dots, so this has
+/// twice the horizontal and four times the vertical resolution of the other charts.
+pub fn braille_chart(points: &[HistoryItem], style: &ChartStyle) -> String {
+    // The bit for each dot, indexed by [column][row from the top]
+    const DOTS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+    if style.width == 0 || style.height == 0 {
+        return String::new();
+    }
+    let dot_rows = style.height * 4;
+    let mut cells = vec![vec![0u8; style.width]; style.height];
+
+    let dots = resample(points, style.width * 2)
+        .into_iter()
+        .map(|c| {
+            c.map(|(value, _)| {
+                dot_rows - 1 - (style.level(value) * (dot_rows - 1) as f64).round() as usize
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut previous = None;
+    for (x, y) in dots.iter().enumerate() {
+        let Some(y) = *y else {
+            previous = None;
+            continue;
+        };
+        // Fill in between this dot and the last one, so steep changes stay connected
+        let (from, to) = match previous {
+            Some(p) if p < y => (p + 1, y),
+            Some(p) if p > y => (y, p - 1),
+            _ => (y, y),
+        };
+        for dot_y in from..=to {
+            cells[dot_y / 4][x / 2] |= DOTS[x % 2][dot_y % 4];
+        }
+        previous = Some(y);
+    }
+
+    let rows = cells
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|bits| char::from_u32(0x2800 + bits as u32).unwrap_or(' '))
+                .collect::<String>()
+        })
+        .collect();
+
+    style.finish(rows, &resample(points, style.width), points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(points: &[(u64, f64, BatteryState)]) -> Vec<HistoryItem> {
+        points
+            .iter()
+            .map(|&(time, value, state)| HistoryItem { time, value, state })
+            .collect()
+    }
+
+    fn plain(width: usize, height: usize) -> ChartStyle {
+        ChartStyle {
+            width,
+            height,
+            labels: false,
+            state_markers: false,
+            ..ChartStyle::default()
+        }
+    }
+
+    #[test]
+    fn resamples_into_columns() {
+        use BatteryState::{Charging as C, Discharging as D};
+
+        assert_eq!(resample(&[], 3), vec![None; 3]);
+        // Points in the same column are averaged, and the last one decides the state
+        assert_eq!(
+            resample(&history(&[(0, 10.0, D), (1, 20.0, C), (2, 60.0, D)]), 2),
+            vec![Some((15.0, C)), Some((60.0, D))]
+        );
+        // Empty columns repeat the one before
+        assert_eq!(
+            resample(&history(&[(0, 10.0, D), (99, 50.0, C)]), 4),
+            vec![
+                Some((10.0, D)),
+                Some((10.0, D)),
+                Some((10.0, D)),
+                Some((50.0, C))
+            ]
+        );
+        assert_eq!(
+            resample(&history(&[(0, 10.0, D), (99, 50.0, C)]), 1),
+            vec![Some((30.0, C))]
+        );
+    }
+
+    #[test]
+    fn sparklines() {
+        let ramp = history(
+            &(0..8)
+                .map(|i| (i, i as f64 * 100.0 / 7.0, BatteryState::Charging))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(sparkline(&ramp, &plain(8, 1)), "▁▂▃▄▅▆▇█");
+        assert_eq!(sparkline(&ramp, &plain(1, 1)), "▅");
+        assert_eq!(sparkline(&ramp, &plain(0, 1)), "");
+        assert_eq!(sparkline(&[], &plain(4, 1)), "    ");
+
+        let gap = history(&[
+            (0, 0.0, BatteryState::Discharging),
+            (90, 100.0, BatteryState::Charging),
+        ]);
+        assert_eq!(sparkline(&gap, &plain(10, 1)), "▁▁▁▁▁▁▁▁▁█");
+    }
+
+    #[test]
+    fn block_bars_stack_up() {
+        let points = history(&[
+            (0, 0.0, BatteryState::Discharging),
+            (1, 25.0, BatteryState::Discharging),
+            (2, 50.0, BatteryState::Discharging),
+            (3, 100.0, BatteryState::Charging),
+        ]);
+        assert_eq!(block_bars(&points, &plain(4, 2)), "   █\n ▄██");
+        assert_eq!(block_bars(&points, &plain(1, 2)), " \n▇");
+        assert_eq!(block_bars(&points, &plain(0, 2)), "");
+        assert_eq!(block_bars(&points, &plain(4, 0)), "");
+        assert_eq!(block_bars(&[], &plain(2, 2)), "  \n  ");
+    }
+
+    #[test]
+    fn braille_lines_stay_connected() {
+        let points = history(&[
+            (0, 0.0, BatteryState::Charging),
+            (1, 100.0, BatteryState::Charging),
+        ]);
+        // The bottom left dot, then the whole right column up to the top
+        assert_eq!(braille_chart(&points, &plain(1, 1)), "⡸");
+        assert_eq!(braille_chart(&points, &plain(2, 1)), "⣀⠏");
+        assert_eq!(braille_chart(&points, &plain(0, 1)), "");
+        assert_eq!(braille_chart(&[], &plain(2, 1)), "⠀⠀");
+
+        let gap = history(&[
+            (0, 100.0, BatteryState::Discharging),
+            (3600, 0.0, BatteryState::Discharging),
+        ]);
+        assert_eq!(braille_chart(&gap, &plain(2, 1)), "⠉⢱");
+    }
+
+    #[test]
+    fn labels_and_markers() {
+        let points = history(&[
+            (0, 100.0, BatteryState::Discharging),
+            (3600, 50.0, BatteryState::Discharging),
+            (7200, 60.0, BatteryState::Charging),
+            (8100, 70.0, BatteryState::Charging),
+        ]);
+        let style = ChartStyle {
+            width: 12,
+            height: 3,
+            ..ChartStyle::default()
+        };
+        assert_eq!(
+            block_bars(&points, &style),
+            "100%│█████      ▁\n    │█████▄▄▄▄▄▆█\n  0%│████████████\n     ----------++\n     -2h 15m    0"
+        );
+        // A single row gets the whole range as its label
+        assert_eq!(
+            sparkline(&points, &style),
+            "0-100%│█████▅▅▅▅▅▅▆\n       ----------++\n       -2h 15m    0"
+        );
+        // Without points there's nothing to put on the time axis
+        assert_eq!(
+            sparkline(&[], &style),
+            "0-100%│            \n                   "
+        );
+        assert_eq!(
+            sparkline(&points, &ChartStyle { width: 1, ..style }),
+            "0-100%│▆\n       +"
+        );
+    }
+}
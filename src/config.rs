@@ -0,0 +1,388 @@
+//! A parser for UPower's own policy config, usually found at `/etc/UPower/UPower.conf`.
+//!
+//! This is what decides when the warning levels kick in, and what happens when the battery runs out.
+//! Source: https://gitlab.freedesktop.org/upower/upower/-/blob/master/etc/UPower.conf
+use {
+    crate::{
+        logging::*,
+        types::{CriticalAction, IntSeconds, Percentage},
+    },
+    ::core::{fmt, str::FromStr},
+    ::std::path::Path,
+};
+
+/// The path that upowerd reads its config from
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/UPower/UPower.conf";
+
+/// The settings in the `[UPower]` group of `UPower.conf`.
+///
+/// Any keys that are missing from the file get UPower's documented defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UPowerConfig {
+    /// Don't poll the kernel for battery level changes
+    pub no_poll_batteries: bool,
+    /// Do we ignore the lid state
+    pub ignore_lid: bool,
+    /// Policy should be based on the percentage instead of the time remaining
+    pub use_percentage_for_policy: bool,
+    /// When `use_percentage_for_policy` is true, the battery is considered low at this level
+    pub percentage_low: Percentage,
+    /// When `use_percentage_for_policy` is true, the battery is considered critical at this level
+    pub percentage_critical: Percentage,
+    /// When `use_percentage_for_policy` is true, the critical power action runs at this level
+    pub percentage_action: Percentage,
+    /// When `use_percentage_for_policy` is false, the battery is considered low with this much time left
+    pub time_low: IntSeconds,
+    /// When `use_percentage_for_policy` is false, the battery is considered critical with this much time left
+    pub time_critical: IntSeconds,
+    /// When `use_percentage_for_policy` is false, the critical power action runs with this much time left
+    pub time_action: IntSeconds,
+    /// Allow risky critical power actions like Suspend
+    pub allow_risky_critical_power_action: bool,
+    /// What to do when the battery reaches the action level
+    pub critical_power_action: CriticalAction,
+}
+impl UPowerConfig {
+    pub const DEFAULT_PERCENTAGE_LOW: Percentage = Percentage::new_saturating(20);
+    pub const DEFAULT_PERCENTAGE_CRITICAL: Percentage = Percentage::new_saturating(5);
+    pub const DEFAULT_PERCENTAGE_ACTION: Percentage = Percentage::new_saturating(2);
+    pub const DEFAULT_TIME_LOW: IntSeconds = IntSeconds::new_from_unsigned(1200);
+    pub const DEFAULT_TIME_CRITICAL: IntSeconds = IntSeconds::new_from_unsigned(300);
+    pub const DEFAULT_TIME_ACTION: IntSeconds = IntSeconds::new_from_unsigned(120);
+
+    /// Read, parse and [validate](Self::validate) the config file at `path`. Use [`DEFAULT_CONFIG_PATH`] for the system config.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let contents = ::std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        contents.parse()
+    }
+
+    /// Check that the low, critical and action levels are in descending order, like upowerd requires.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(self.percentage_low > self.percentage_critical
+            && self.percentage_critical > self.percentage_action)
+        {
+            return Err(ConfigError::PercentageOrder);
+        }
+
+        if !(self.time_low.get() > self.time_critical.get()
+            && self.time_critical.get() > self.time_action.get())
+        {
+            return Err(ConfigError::TimeOrder);
+        }
+
+        Ok(())
+    }
+
+    /// Get the settings that upowerd will actually use.
+    ///
+    /// If the levels are in the wrong order, upowerd ignores them and falls back to its defaults.
+    /// It also falls back to `HybridSleep` if the critical action is `Suspend` or `Ignore`
+    /// without `AllowRiskyCriticalPowerAction`, or if the action isn't recognized.
+    pub fn effective(&self) -> Self {
+        let mut me = *self;
+        let defaults = Self::default();
+
+        if me.validate() == Err(ConfigError::PercentageOrder) {
+            me.percentage_low = defaults.percentage_low;
+            me.percentage_critical = defaults.percentage_critical;
+            me.percentage_action = defaults.percentage_action;
+        }
+        if me.validate() == Err(ConfigError::TimeOrder) {
+            me.time_low = defaults.time_low;
+            me.time_critical = defaults.time_critical;
+            me.time_action = defaults.time_action;
+        }
+
+        let is_risky = matches!(
+            me.critical_power_action,
+            CriticalAction::Suspend | CriticalAction::Ignore
+        );
+        if (is_risky && !me.allow_risky_critical_power_action)
+            || me.critical_power_action == CriticalAction::Unknown
+        {
+            me.critical_power_action = defaults.critical_power_action;
+        }
+
+        me
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "NoPollBatteries" => self.no_poll_batteries = parse_bool(key, value)?,
+            "IgnoreLid" => self.ignore_lid = parse_bool(key, value)?,
+            "UsePercentageForPolicy" => self.use_percentage_for_policy = parse_bool(key, value)?,
+            "PercentageLow" => self.percentage_low = parse_percentage(key, value)?,
+            "PercentageCritical" => self.percentage_critical = parse_percentage(key, value)?,
+            "PercentageAction" => self.percentage_action = parse_percentage(key, value)?,
+            "TimeLow" => self.time_low = parse_seconds(key, value)?,
+            "TimeCritical" => self.time_critical = parse_seconds(key, value)?,
+            "TimeAction" => self.time_action = parse_seconds(key, value)?,
+            "AllowRiskyCriticalPowerAction" => {
+                self.allow_risky_critical_power_action = parse_bool(key, value)?
+            }
+            "CriticalPowerAction" => {
+                self.critical_power_action = CriticalAction::from_str(value)
+                    .map_err(|_| ConfigError::invalid_value(key, value))?
+            }
+            _ => debug!("Ignoring unknown UPower config key {}", key),
+        }
+
+        Ok(())
+    }
+}
+impl Default for UPowerConfig {
+    fn default() -> Self {
+        Self {
+            no_poll_batteries: false,
+            ignore_lid: false,
+            use_percentage_for_policy: true,
+            percentage_low: Self::DEFAULT_PERCENTAGE_LOW,
+            percentage_critical: Self::DEFAULT_PERCENTAGE_CRITICAL,
+            percentage_action: Self::DEFAULT_PERCENTAGE_ACTION,
+            time_low: Self::DEFAULT_TIME_LOW,
+            time_critical: Self::DEFAULT_TIME_CRITICAL,
+            time_action: Self::DEFAULT_TIME_ACTION,
+            allow_risky_critical_power_action: false,
+            critical_power_action: CriticalAction::HybridSleep,
+        }
+    }
+}
+impl FromStr for UPowerConfig {
+    type Err = ConfigError;
+    /// Parse the contents of a config file. Only the `[UPower]` group is read.
+    ///
+    /// Levels in the wrong order are an error. Use [`UPowerConfig::effective`] on a config you built yourself
+    /// to see what upowerd would do with them instead.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut me = Self::default();
+        let mut in_upower_group = false;
+
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(group) = line.strip_prefix('[') {
+                let group = group
+                    .strip_suffix(']')
+                    .ok_or(ConfigError::Syntax(index + 1))?;
+                in_upower_group = group == "UPower";
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or(ConfigError::Syntax(index + 1))?;
+            if in_upower_group {
+                me.set(key.trim(), value.trim())?;
+            }
+        }
+
+        me.validate()?;
+        Ok(me)
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(ConfigError::invalid_value(key, value)),
+    }
+}
+
+/// UPower reads percentages as doubles, even though they're usually written as integers.
+fn parse_percentage(key: &str, value: &str) -> Result<Percentage, ConfigError> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(Percentage::from_f64)
+        .ok_or_else(|| ConfigError::invalid_value(key, value))
+}
+
+fn parse_seconds(key: &str, value: &str) -> Result<IntSeconds, ConfigError> {
+    value
+        .parse::<u64>()
+        .map(IntSeconds::new_from_unsigned)
+        .map_err(|_| ConfigError::invalid_value(key, value))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(::std::io::Error),
+    /// The line at this (1-based) line number is not a group header or a key-value pair
+    Syntax(usize),
+    InvalidValue {
+        key: String,
+        value: String,
+    },
+    /// The percentages are not in the order `PercentageLow > PercentageCritical > PercentageAction`
+    PercentageOrder,
+    /// The times are not in the order `TimeLow > TimeCritical > TimeAction`
+    TimeOrder,
+}
+impl ConfigError {
+    fn invalid_value(key: &str, value: &str) -> Self {
+        Self::InvalidValue {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+}
+impl PartialEq for ConfigError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Io(l), Self::Io(r)) => l.kind() == r.kind(),
+            (Self::Syntax(l), Self::Syntax(r)) => l == r,
+            (
+                Self::InvalidValue { key, value },
+                Self::InvalidValue {
+                    key: other_key,
+                    value: other_value,
+                },
+            ) => key == other_key && value == other_value,
+            (Self::PercentageOrder, Self::PercentageOrder) | (Self::TimeOrder, Self::TimeOrder) => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Failed to read UPower config: {e}"),
+            Self::Syntax(line) => write!(f, "Syntax error in UPower config on line {line}"),
+            Self::InvalidValue { key, value } => write!(f, "Invalid value for {key}: {value}"),
+            Self::PercentageOrder => f.write_str(
+                "PercentageLow, PercentageCritical and PercentageAction must be in descending order",
+            ),
+            Self::TimeOrder => {
+                f.write_str("TimeLow, TimeCritical and TimeAction must be in descending order")
+            }
+        }
+    }
+}
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Result<UPowerConfig, ConfigError> {
+        UPowerConfig::from_path(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name),
+        )
+    }
+
+    #[test]
+    fn default_file_matches_defaults() {
+        assert_eq!(fixture("UPower.conf").unwrap(), UPowerConfig::default());
+    }
+
+    #[test]
+    fn custom_file() {
+        let config = fixture("UPower-custom.conf").unwrap();
+        assert!(!config.use_percentage_for_policy);
+        // The other group doesn't count
+        assert_eq!(config.percentage_low, Percentage::new_saturating(30));
+        assert_eq!(config.percentage_critical, Percentage::new_saturating(10));
+        assert_eq!(config.percentage_action, Percentage::new_saturating(5));
+        assert_eq!(config.time_low, IntSeconds::new_from_unsigned(1800));
+        assert_eq!(config.time_critical, IntSeconds::new_from_unsigned(600));
+        assert_eq!(config.time_action, IntSeconds::new_from_unsigned(180));
+        assert_eq!(config.critical_power_action, CriticalAction::Suspend);
+        assert_eq!(config.effective(), config);
+    }
+
+    #[test]
+    fn inverted_levels_are_rejected() {
+        assert_eq!(
+            fixture("UPower-inverted.conf"),
+            Err(ConfigError::PercentageOrder)
+        );
+        assert_eq!(
+            "[UPower]\nTimeLow=100\nTimeCritical=300".parse::<UPowerConfig>(),
+            Err(ConfigError::TimeOrder)
+        );
+    }
+
+    #[test]
+    fn effective_falls_back_like_upowerd() {
+        let inverted = UPowerConfig {
+            percentage_low: Percentage::new_saturating(5),
+            percentage_critical: Percentage::new_saturating(10),
+            ..Default::default()
+        };
+        let effective = inverted.effective();
+        assert_eq!(
+            effective.percentage_low,
+            UPowerConfig::DEFAULT_PERCENTAGE_LOW
+        );
+        assert_eq!(
+            effective.percentage_critical,
+            UPowerConfig::DEFAULT_PERCENTAGE_CRITICAL
+        );
+
+        let risky = fixture("UPower-risky.conf").unwrap();
+        assert_eq!(risky.critical_power_action, CriticalAction::Suspend);
+        assert_eq!(
+            risky.effective().critical_power_action,
+            CriticalAction::HybridSleep
+        );
+
+        for (action, allowed, expected) in [
+            (CriticalAction::Ignore, false, CriticalAction::HybridSleep),
+            (CriticalAction::Ignore, true, CriticalAction::Ignore),
+            (CriticalAction::Suspend, true, CriticalAction::Suspend),
+            (CriticalAction::PowerOff, false, CriticalAction::PowerOff),
+            (CriticalAction::Hibernate, false, CriticalAction::Hibernate),
+            (CriticalAction::Unknown, true, CriticalAction::HybridSleep),
+        ] {
+            let config = UPowerConfig {
+                critical_power_action: action,
+                allow_risky_critical_power_action: allowed,
+                ..Default::default()
+            };
+            assert_eq!(
+                config.effective().critical_power_action,
+                expected,
+                "{action}"
+            );
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            "[UPower]\nPercentageLow".parse::<UPowerConfig>(),
+            Err(ConfigError::Syntax(2))
+        );
+        assert_eq!(
+            "[UPower\n".parse::<UPowerConfig>(),
+            Err(ConfigError::Syntax(1))
+        );
+        assert_eq!(
+            "[UPower]\nIgnoreLid=maybe".parse::<UPowerConfig>(),
+            Err(ConfigError::InvalidValue {
+                key: "IgnoreLid".to_owned(),
+                value: "maybe".to_owned()
+            })
+        );
+        assert_eq!(
+            "[UPower]\nPercentageLow=120".parse::<UPowerConfig>(),
+            Err(ConfigError::InvalidValue {
+                key: "PercentageLow".to_owned(),
+                value: "120".to_owned()
+            })
+        );
+    }
+}
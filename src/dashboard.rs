@@ -0,0 +1,789 @@
+//! A full-screen overview of every power device, for `upowerz dashboard`.
+//!
+//! Everything is drawn with plain ANSI escapes and box-drawing characters, so it works in any terminal, including over SSH.
+//! This only builds the screen and reacts to keys and to the changes from [`watch`], so nothing is polled.
+//! Putting the terminal in raw mode and reading input is up to the caller.
+use {
+    crate::{
+        backlight,
+        chart::{short_duration, sparkline, ChartStyle},
+        dump::DeviceText,
+        health::{HealthInput, HealthReport, HealthThresholds},
+        history::{HistoryItem, HistoryKind},
+        logging::*,
+        style::{Markup, Palette, Rgb},
+        types::{BatteryState, DeviceType, UPowerName},
+        xmlgen::{
+            device::PowerDeviceProxy, keyboard::KbdBacklightProxy, upower::UPowerProxy,
+            DeviceDetails,
+        },
+    },
+    ::core::{cmp::Ordering, time::Duration},
+    ::futures_util::{
+        future::ready,
+        stream::{self, BoxStream, SelectAll},
+        Stream, StreamExt,
+    },
+    ::std::collections::HashSet,
+    ::zbus::{fdo::PropertiesProxy, zvariant::OwnedObjectPath},
+};
+
+/// How far back the history charts go
+pub const HISTORY_SPAN: Duration = Duration::from_secs(24 * 60 * 60);
+/// Roughly how many history points to ask for. The charts are resampled to fit, so this only needs to beat the terminal width.
+const HISTORY_RESOLUTION: u32 = 200;
+
+/// What a key press does
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    Down,
+    /// Ask upowerd to read the selected device again, then request everything
+    Refresh,
+    BacklightUp,
+    BacklightDown,
+    BacklightCycle,
+    Quit,
+}
+impl Action {
+    /// Recognize every key press in a chunk of raw terminal input, in order.
+    ///
+    /// A single read can hold several keys, when typing fast or pasting. Escape sequences that aren't recognized
+    /// are skipped whole, so the letters in them don't count as keys.
+    pub fn from_input(mut input: &[u8]) -> Vec<Self> {
+        let mut actions = Vec::new();
+
+        while let Some((&first, rest)) = input.split_first() {
+            let (key, rest) = match (first, rest) {
+                // CSI: any number of parameter bytes, then a final byte from `@` to `~`
+                (b'\x1b', [b'[', sequence @ ..]) => {
+                    let end = sequence
+                        .iter()
+                        .position(|b| (0x40..=0x7e).contains(b))
+                        .map_or(sequence.len(), |i| i + 1);
+                    (&input[..2 + end], &sequence[end..])
+                }
+                // SS3: exactly one more byte
+                (b'\x1b', [b'O', _, rest @ ..]) => (&input[..3], rest),
+                _ => (&input[..1], rest),
+            };
+            actions.extend(Self::from_key(key));
+            input = rest;
+        }
+
+        actions
+    }
+
+    /// Recognize a single key. The arrow keys are accepted in both normal and application cursor mode.
+    fn from_key(key: &[u8]) -> Option<Self> {
+        match key {
+            b"\x1b[A" | b"\x1bOA" | b"k" => Some(Self::Up),
+            b"\x1b[B" | b"\x1bOB" | b"j" => Some(Self::Down),
+            b"r" => Some(Self::Refresh),
+            b"+" | b"=" => Some(Self::BacklightUp),
+            b"-" => Some(Self::BacklightDown),
+            b"b" => Some(Self::BacklightCycle),
+            // Ctrl+C doesn't send a signal in raw mode
+            b"q" | b"\x03" => Some(Self::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Something on screen changed, from [`watch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Any property of this device
+    Device(OwnedObjectPath),
+    Added(OwnedObjectPath),
+    Removed(OwnedObjectPath),
+    /// Whether the system is on battery, or the lid
+    Daemon,
+    /// The keyboard backlight's brightness
+    Backlight,
+}
+
+/// Watch everything the dashboard shows, including devices that get connected later.
+///
+/// Start this before [`Dashboard::refresh`], so nothing that changes in between is missed.
+pub async fn watch(
+    connection: ::zbus::Connection,
+) -> ::zbus::Result<impl Stream<Item = Change> + Send + 'static> {
+    let upower = UPowerProxy::new(&connection).await?;
+
+    let mut changes: SelectAll<BoxStream<'static, Change>> = SelectAll::new();
+    changes.push(
+        upower
+            .receive_device_added()
+            .await?
+            .filter_map(|signal| {
+                ready(
+                    signal
+                        .args()
+                        .ok()
+                        .map(|args| Change::Added(args.device.into())),
+                )
+            })
+            .boxed(),
+    );
+    changes.push(
+        upower
+            .receive_device_removed()
+            .await?
+            .filter_map(|signal| {
+                ready(
+                    signal
+                        .args()
+                        .ok()
+                        .map(|args| Change::Removed(args.device.into())),
+                )
+            })
+            .boxed(),
+    );
+    changes.push(
+        stream::select(
+            upower.receive_on_battery_changed().await.map(|_| ()),
+            stream::select(
+                upower.receive_lid_is_closed_changed().await.map(|_| ()),
+                upower.receive_lid_is_present_changed().await.map(|_| ()),
+            ),
+        )
+        .map(|()| Change::Daemon)
+        .boxed(),
+    );
+    // Not every machine has a keyboard backlight
+    if let Ok(backlight) = KbdBacklightProxy::new(&connection).await {
+        if let Ok(brightness) = backlight.receive_brightness_changed().await {
+            changes.push(brightness.map(|_| Change::Backlight).boxed());
+        }
+    }
+
+    // Property streams don't end when a device is removed, so a device that comes back can reuse its old one.
+    let mut watched = HashSet::<OwnedObjectPath>::new();
+    for path in upower.enumerate_devices().await? {
+        match watch_device(&connection, path.clone()).await {
+            Ok(device) => {
+                changes.push(device);
+                watched.insert(path);
+            }
+            Err(e) => warning!("Failed to watch {}: {}", path.as_str(), e),
+        }
+    }
+
+    let events = stream::unfold(
+        (connection, changes, watched),
+        |(connection, mut changes, mut watched)| async move {
+            let change = changes.next().await?;
+            if let Change::Added(path) = &change {
+                if watched.insert(path.clone()) {
+                    match watch_device(&connection, path.clone()).await {
+                        Ok(device) => changes.push(device),
+                        Err(e) => {
+                            warning!("Failed to watch {}: {}", path.as_str(), e);
+                            watched.remove(path);
+                        }
+                    }
+                }
+            }
+            Some((change, (connection, changes, watched)))
+        },
+    );
+
+    Ok(events)
+}
+
+/// A [`Change::Device`] every time any of the device's properties change
+async fn watch_device(
+    connection: &::zbus::Connection,
+    path: OwnedObjectPath,
+) -> ::zbus::Result<BoxStream<'static, Change>> {
+    let proxy = PropertiesProxy::new(connection, "org.freedesktop.UPower", path.clone()).await?;
+    let changes = proxy.receive_properties_changed().await?;
+    Ok(changes.map(move |_| Change::Device(path.clone())).boxed())
+}
+
+/// One row of the device table
+#[derive(Debug, Clone)]
+pub struct DeviceEntry {
+    pub path: OwnedObjectPath,
+    pub details: DeviceDetails,
+    /// Only for laptop batteries
+    pub health: Option<HealthReport>,
+}
+impl DeviceEntry {
+    /// The device type and its model, or the last part of its native path if it has no model, like `battery BAT0`
+    pub fn name(&self) -> String {
+        let details = &self.details;
+        let model = match details.model.is_empty() {
+            true => details.native_path.rsplit('/').next().unwrap_or_default(),
+            false => &details.model,
+        };
+        format!("{} {}", details.type_.upower_name(), model)
+    }
+}
+
+/// Everything on screen
+#[derive(Debug, Clone, Default)]
+pub struct Dashboard {
+    pub devices: Vec<DeviceEntry>,
+    /// An index into `devices`
+    pub selected: usize,
+    pub on_battery: bool,
+    /// None if there is no lid
+    pub lid_closed: Option<bool>,
+    /// `(brightness, max brightness)`, or None if there is no keyboard backlight
+    pub kbd_backlight: Option<(i32, i32)>,
+    /// The selected device's charge history, oldest first
+    pub charge_history: Vec<HistoryItem>,
+    /// The selected device's energy rate history, oldest first
+    pub rate_history: Vec<HistoryItem>,
+    /// Shown in the footer instead of the key help, like the result of the last action
+    pub status: Option<String>,
+    pub palette: Palette,
+}
+impl Dashboard {
+    const KEY_HELP: &'static str =
+        "↑/↓ select  r refresh  +/- keyboard backlight  b cycle backlight  q quit";
+
+    #[inline]
+    pub fn selected(&self) -> Option<&DeviceEntry> {
+        self.devices.get(self.selected)
+    }
+
+    /// Request everything again, keeping the same device selected if it is still there
+    pub async fn refresh(&mut self, connection: &::zbus::Connection) -> ::zbus::Result<()> {
+        let upower = UPowerProxy::new(connection).await?;
+        self.request_daemon(&upower).await?;
+
+        let selected = self.selected().map(|d| d.path.clone());
+        let mut devices = Vec::new();
+        for path in upower.enumerate_devices().await? {
+            match Self::request_device(connection, path.clone()).await {
+                Ok(entry) => devices.push(entry),
+                Err(e) => warning!("Failed to get device details for {}: {}", path.as_str(), e),
+            }
+        }
+
+        self.selected = selected
+            .and_then(|path| devices.iter().position(|d| d.path == path))
+            .unwrap_or(self.selected.min(devices.len().saturating_sub(1)));
+        self.devices = devices;
+
+        self.kbd_backlight = Self::request_backlight(connection).await;
+        self.request_history(connection).await;
+        Ok(())
+    }
+
+    /// React to a change from [`watch`]. Only what changed is requested again, and the history only if
+    /// the selection moved to another device.
+    pub async fn update(&mut self, connection: &::zbus::Connection, change: Change) {
+        match change {
+            Change::Device(path) | Change::Added(path) => {
+                let entry = match Self::request_device(connection, path.clone()).await {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        warning!("Failed to get device details for {}: {}", path.as_str(), e);
+                        return;
+                    }
+                };
+                match self.devices.iter_mut().find(|d| d.path == path) {
+                    Some(existing) => *existing = entry,
+                    None => {
+                        self.devices.push(entry);
+                        // The first device is selected right away
+                        if self.devices.len() == 1 {
+                            self.request_history(connection).await;
+                        }
+                    }
+                }
+            }
+            Change::Removed(path) => {
+                if self.remove(&path) {
+                    self.request_history(connection).await;
+                }
+            }
+            Change::Daemon => {
+                let result = match UPowerProxy::new(connection).await {
+                    Ok(upower) => self.request_daemon(&upower).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    self.status = Some(format!("Failed to refresh: {e}"));
+                }
+            }
+            Change::Backlight => self.kbd_backlight = Self::request_backlight(connection).await,
+        }
+    }
+
+    /// Drop a device from the table. Returns true if the selection moved to another device.
+    fn remove(&mut self, path: &OwnedObjectPath) -> bool {
+        let Some(index) = self.devices.iter().position(|d| &d.path == path) else {
+            return false;
+        };
+        self.devices.remove(index);
+
+        match index.cmp(&self.selected) {
+            Ordering::Less => {
+                self.selected -= 1;
+                false
+            }
+            Ordering::Equal => {
+                self.selected = self.selected.min(self.devices.len().saturating_sub(1));
+                true
+            }
+            Ordering::Greater => false,
+        }
+    }
+
+    async fn request_daemon(&mut self, upower: &UPowerProxy<'_>) -> ::zbus::Result<()> {
+        let (on_battery, lid_is_present, lid_is_closed) = ::futures_util::join!(
+            upower.on_battery(),
+            upower.lid_is_present(),
+            upower.lid_is_closed(),
+        );
+        self.on_battery = on_battery?;
+        self.lid_closed = match lid_is_present? {
+            true => Some(lid_is_closed?),
+            false => None,
+        };
+        Ok(())
+    }
+
+    /// A device's details, and its health if it is a laptop battery
+    async fn request_device(
+        connection: &::zbus::Connection,
+        path: OwnedObjectPath,
+    ) -> ::zbus::Result<DeviceEntry> {
+        let proxy = PowerDeviceProxy::new(connection, path.clone()).await?;
+        let details = DeviceDetails::request_all(&proxy).await.try_resolve()?;
+        let health = match details.type_ == DeviceType::Battery && details.power_supply {
+            true => HealthInput::request(&proxy)
+                .await
+                .ok()
+                .map(|input| HealthReport::new(input, &HealthThresholds::default())),
+            false => None,
+        };
+        Ok(DeviceEntry {
+            path,
+            details,
+            health,
+        })
+    }
+
+    async fn request_backlight(connection: &::zbus::Connection) -> Option<(i32, i32)> {
+        let proxy = KbdBacklightProxy::new(connection).await.ok()?;
+        let (current, max) =
+            ::futures_util::join!(proxy.get_brightness(), proxy.get_max_brightness());
+        Some((current.ok()?, max.ok()?))
+    }
+
+    /// Get the selected device's history. Devices without history just get empty charts.
+    pub async fn request_history(&mut self, connection: &::zbus::Connection) {
+        self.charge_history.clear();
+        self.rate_history.clear();
+
+        let Some(path) = self.selected().map(|d| d.path.clone()) else {
+            return;
+        };
+        let Ok(proxy) = PowerDeviceProxy::new(connection, path).await else {
+            return;
+        };
+        if !proxy.has_history().await.unwrap_or_default() {
+            return;
+        }
+
+        let (charge, rate) = ::futures_util::join!(
+            HistoryKind::Charge.request(&proxy, HISTORY_SPAN, HISTORY_RESOLUTION),
+            HistoryKind::Rate.request(&proxy, HISTORY_SPAN, HISTORY_RESOLUTION),
+        );
+        self.charge_history = charge.unwrap_or_default();
+        self.rate_history = rate.unwrap_or_default();
+    }
+
+    /// React to a key press. Returns false once the dashboard should close.
+    ///
+    /// Errors are shown in the footer instead of being returned, so a missing backlight doesn't end the session.
+    pub async fn apply(&mut self, connection: &::zbus::Connection, action: Action) -> bool {
+        self.status = None;
+
+        match action {
+            Action::Up | Action::Down => {
+                let selected = match action {
+                    Action::Up => self.selected.saturating_sub(1),
+                    _ => (self.selected + 1).min(self.devices.len().saturating_sub(1)),
+                };
+                if selected != self.selected {
+                    self.selected = selected;
+                    self.request_history(connection).await;
+                }
+            }
+            Action::Refresh => {
+                if let Some(path) = self.selected().map(|d| d.path.clone()) {
+                    let result = match PowerDeviceProxy::new(connection, path).await {
+                        Ok(proxy) => proxy.refresh().await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        self.status = Some(format!("Failed to refresh the device: {e}"));
+                    }
+                }
+                if let Err(e) = self.refresh(connection).await {
+                    self.status = Some(format!("Failed to refresh: {e}"));
+                }
+            }
+            Action::BacklightUp | Action::BacklightDown | Action::BacklightCycle => {
+                let result = match KbdBacklightProxy::new(connection).await {
+                    Ok(proxy) => match action {
+                        Action::BacklightUp => backlight::step(&proxy, 1).await,
+                        Action::BacklightDown => backlight::step(&proxy, -1).await,
+                        _ => backlight::cycle(&proxy).await,
+                    },
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(brightness) => {
+                        self.kbd_backlight = Self::request_backlight(connection).await;
+                        self.status = Some(format!("Keyboard backlight set to {brightness}"));
+                    }
+                    Err(e) => {
+                        self.status = Some(format!("Failed to set the keyboard backlight: {e}"))
+                    }
+                }
+            }
+            Action::Quit => return false,
+        }
+
+        true
+    }
+
+    /// Draw the whole screen for a terminal this size.
+    ///
+    /// This starts in the top left corner and clears everything it doesn't draw over,
+    /// so it can be written straight to the terminal without clearing it first, which would flicker.
+    pub fn render(&self, width: usize, height: usize) -> String {
+        let mut lines = Vec::new();
+
+        let mut header = Line::new(width);
+        header.push(" upowerz  │  ", None);
+        match self.on_battery {
+            true => header.push("On battery", self.palette.low),
+            false => header.push("On AC power", self.palette.charging),
+        };
+        header.push("  │  ", None);
+        header.push(
+            match self.lid_closed {
+                Some(true) => "Lid closed",
+                Some(false) => "Lid open",
+                None => "No lid",
+            },
+            None,
+        );
+        header.push("  │  ", None);
+        header.push(
+            &match self.kbd_backlight {
+                Some((brightness, max)) => format!("Keyboard backlight {brightness}/{max}"),
+                None => "No keyboard backlight".to_owned(),
+            },
+            None,
+        );
+        lines.push(format!("\x1b[1m{}\x1b[22m", header.finish()));
+        lines.push("─".repeat(width));
+
+        lines.extend(self.render_table(width, height));
+        lines.push("─".repeat(width));
+
+        // The footer takes the last line
+        let pane_height = height.saturating_sub(lines.len() + 1);
+        lines.extend(self.render_pane(width, pane_height));
+        lines.truncate(height.saturating_sub(1));
+        lines.resize(height.saturating_sub(1), String::new());
+
+        let mut footer = Line::new(width);
+        footer.push(" ", None);
+        footer.push(self.status.as_deref().unwrap_or(Self::KEY_HELP), None);
+        lines.push(format!("\x1b[7m{}\x1b[27m", footer.finish()));
+
+        let mut frame = String::from("\x1b[H");
+        frame.push_str(&lines.join("\x1b[K\r\n"));
+        frame.push_str("\x1b[K\x1b[J");
+        frame
+    }
+
+    /// The column headers and one row per device, scrolled so the selected one is visible
+    fn render_table(&self, width: usize, height: usize) -> Vec<String> {
+        const NAME: usize = 24;
+        const STATE: usize = 17;
+        const RATE: usize = 9;
+        const TIME: usize = 8;
+        // The marker, every column but the gauge, and the spaces between them
+        const FIXED: usize = 2 + NAME + 1 + 4 + 1 + STATE + 1 + RATE + 1 + TIME + 1 + 16;
+        let gauge = width.saturating_sub(FIXED + 1).min(30);
+
+        let mut lines = Vec::new();
+        let mut header = Line::new(width);
+        header.push(&format!("  {:<NAME$} ", "Device"), None);
+        if gauge > 0 {
+            header.push(&format!("{:<gauge$} ", "Charge"), None);
+        }
+        header.push(
+            &format!(
+                "{:>4} {:<STATE$} {:>RATE$} {:>TIME$} Health",
+                "", "State", "Rate", "Time"
+            ),
+            None,
+        );
+        lines.push(format!("\x1b[1m{}\x1b[22m", header.finish()));
+
+        if self.devices.is_empty() {
+            lines.push("  No devices".to_owned());
+            return lines;
+        }
+
+        // Leave at least half of the screen for the detail pane
+        let rows = (height.saturating_sub(6) / 2).max(1);
+        let first = (self.selected + 1).saturating_sub(rows);
+
+        for (i, entry) in self.devices.iter().enumerate().skip(first).take(rows) {
+            let details = &entry.details;
+            let has_charge = !matches!(details.type_, DeviceType::LinePower | DeviceType::Unknown);
+            let color = self.palette.color(details.state, details.warning_level);
+
+            let mut line = Line::new(width);
+            line.push(
+                match i == self.selected {
+                    true => "> ",
+                    false => "  ",
+                },
+                None,
+            );
+            line.push(&format!("{:<NAME$} ", entry.name()), None);
+            if gauge > 0 {
+                let filled = (details.percentage.get() as usize * gauge + 50) / 100;
+                match has_charge {
+                    true => {
+                        line.push(&"█".repeat(filled), color);
+                        line.push(&format!("{} ", "░".repeat(gauge - filled)), None)
+                    }
+                    false => line.push(&" ".repeat(gauge + 1), None),
+                };
+            }
+            line.push(
+                &match has_charge {
+                    true => format!("{:>4} ", details.percentage.to_string()),
+                    false => format!("{:>4} ", ""),
+                },
+                color,
+            );
+
+            let state = match details.type_ {
+                DeviceType::LinePower => match details.online {
+                    true => "online",
+                    false => "offline",
+                },
+                _ => details.state.upower_name(),
+            };
+            let rate = match details.energy_rate > 0.0 {
+                true => format!("{:.1} W", details.energy_rate),
+                false => String::new(),
+            };
+            let time = match details.state {
+                BatteryState::Charging => details.time_to_full,
+                _ => details.time_to_empty,
+            }
+            .get()
+            .as_secs();
+            let time = match time > 0 {
+                true => short_duration(time),
+                false => String::new(),
+            };
+            let health = match &entry.health {
+                Some(health) => match health.state_of_health.or(health.capacity) {
+                    Some(soh) => format!("{} {soh}", health.class),
+                    None => health.class.to_string(),
+                },
+                None => String::new(),
+            };
+            line.push(
+                &format!("{state:<STATE$} {rate:>RATE$} {time:>TIME$} {health}"),
+                None,
+            );
+
+            lines.push(match i == self.selected {
+                true => format!("\x1b[7m{}\x1b[27m", line.finish()),
+                false => line.finish(),
+            });
+        }
+
+        lines
+    }
+
+    /// Every property of the selected device, and its history charts. They go side by side if there is room.
+    fn render_pane(&self, width: usize, height: usize) -> Vec<String> {
+        let Some(entry) = self.selected() else {
+            return Vec::new();
+        };
+
+        let text = DeviceText::from_details(Some(entry.path.to_string()), &entry.details);
+        let properties = text.to_string();
+        let properties = properties.lines();
+
+        const PROPERTIES_WIDTH: usize = 48;
+        let side_by_side = width >= PROPERTIES_WIDTH + 40;
+        let charts_width = match side_by_side {
+            true => width - PROPERTIES_WIDTH - 2,
+            false => width,
+        };
+        let charts = self.render_charts(charts_width);
+
+        match side_by_side {
+            true => {
+                let mut properties = properties;
+                let mut charts = charts.iter();
+                (0..height)
+                    .map(|_| {
+                        let mut line = Line::new(width);
+                        let mut left = Line::new(PROPERTIES_WIDTH);
+                        left.push(properties.next().unwrap_or_default(), None);
+                        line.push(&left.finish(), None);
+                        line.push("│ ", None);
+                        line.push(charts.next().map_or("", String::as_str), None);
+                        line.finish()
+                    })
+                    .collect()
+            }
+            false => charts
+                .into_iter()
+                .chain(properties.map(str::to_owned))
+                .take(height)
+                .map(|text| {
+                    let mut line = Line::new(width);
+                    line.push(&text, None);
+                    line.finish()
+                })
+                .collect(),
+        }
+    }
+
+    fn render_charts(&self, width: usize) -> Vec<String> {
+        if self.charge_history.is_empty() && self.rate_history.is_empty() {
+            return vec!["No history".to_owned()];
+        }
+
+        // Leave room for the value labels
+        let style = ChartStyle {
+            width: width.saturating_sub(10).max(10),
+            height: 1,
+            ..Default::default()
+        };
+        let max_rate = self
+            .rate_history
+            .iter()
+            .map(|i| i.value.abs())
+            .fold(0.0, f64::max);
+
+        let mut lines = vec![format!("Charge, last {}h", HISTORY_SPAN.as_secs() / 3600)];
+        lines.extend(
+            sparkline(&self.charge_history, &style)
+                .lines()
+                .map(str::to_owned),
+        );
+        lines.push(String::new());
+        lines.push("Rate".to_owned());
+        lines.extend(
+            sparkline(
+                &self.rate_history,
+                &ChartStyle {
+                    max: max_rate.ceil().max(1.0),
+                    unit: " W",
+                    ..style
+                },
+            )
+            .lines()
+            .map(str::to_owned),
+        );
+        lines
+    }
+}
+
+/// A line that is built from left to right, and silently drops whatever doesn't fit
+struct Line {
+    text: String,
+    used: usize,
+    width: usize,
+}
+impl Line {
+    fn new(width: usize) -> Self {
+        Self {
+            text: String::new(),
+            used: 0,
+            width,
+        }
+    }
+
+    /// Add text, without any control characters, since some of it comes from the daemon
+    fn push(&mut self, text: &str, color: Option<Rgb>) -> &mut Self {
+        let text = Markup::Ansi
+            .escape(text)
+            .chars()
+            .take(self.width - self.used)
+            .collect::<String>();
+        self.used += text.chars().count();
+        self.text.push_str(&Markup::Ansi.colorize(&text, color));
+        self
+    }
+
+    /// Pad to the full width, so the reverse video of a selected row reaches the edge
+    fn finish(self) -> String {
+        format!("{}{:pad$}", self.text, "", pad = self.width - self.used)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_key_in_a_chunk_counts() {
+        assert_eq!(
+            Action::from_input(b"jjk"),
+            [Action::Down, Action::Down, Action::Up]
+        );
+        assert_eq!(
+            Action::from_input(b"\x1b[A\x1bOB+q"),
+            [Action::Up, Action::Down, Action::BacklightUp, Action::Quit]
+        );
+        // Page up, and ctrl+up, aren't actions, and neither is anything inside them
+        assert_eq!(Action::from_input(b"\x1b[5~\x1b[1;5Ar"), [Action::Refresh]);
+        assert_eq!(Action::from_input(b"\x1b"), []);
+        assert_eq!(Action::from_input(b"x\x03"), [Action::Quit]);
+    }
+
+    fn entry(name: &str) -> DeviceEntry {
+        DeviceEntry {
+            path: OwnedObjectPath::try_from(format!("/org/freedesktop/UPower/devices/{name}"))
+                .unwrap(),
+            details: DeviceDetails::default(),
+            health: None,
+        }
+    }
+
+    #[test]
+    fn removing_devices_keeps_the_selection() {
+        let mut dashboard = Dashboard {
+            devices: vec![entry("a"), entry("b"), entry("c")],
+            selected: 1,
+            ..Default::default()
+        };
+
+        assert!(!dashboard.remove(&entry("c").path));
+        assert!(!dashboard.remove(&entry("c").path));
+        assert!(!dashboard.remove(&entry("a").path));
+        assert_eq!(
+            dashboard.selected().map(|d| &d.path),
+            Some(&entry("b").path)
+        );
+
+        assert!(dashboard.remove(&entry("b").path));
+        assert!(dashboard.selected().is_none());
+    }
+}
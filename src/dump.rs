@@ -0,0 +1,807 @@
+//! Parse and render the text output of `upower -i <device>` and `upower -d`.
+//!
+//! This is for working with captured dumps (like the ones attached to bug reports) without D-Bus access,
+//! and for producing output that can be diffed against the real thing.
+//!
+//! The renderer follows `up_device_to_text` from libupower-glib. Every line is optional, because which lines
+//! upower prints depends on the kind of device and the version. Parsing a dump and rendering it again gives back the same text.
+use {
+    crate::{
+        history::HistoryItem,
+        logging::*,
+        types::{
+            BatteryLevel, BatteryState, CapacityLevel, CriticalAction, DeviceType, IntSeconds,
+            Percentage, Technology, UPowerName, WarningLevel,
+        },
+        xmlgen::{DeviceDetails, DisplayDeviceDetails},
+    },
+    ::core::fmt::{self, Write},
+};
+
+/// The object path of the composite display device
+pub const DISPLAY_DEVICE_PATH: &str = "/org/freedesktop/UPower/devices/DisplayDevice";
+
+/// A device, as printed by `upower -i`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceText {
+    /// The `Device:` line, only printed by `upower -d`
+    pub object_path: Option<String>,
+    pub native_path: Option<String>,
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub power_supply: Option<bool>,
+    /// Kept as it was printed, because it is formatted in the local time zone, relative to when the dump was taken
+    pub updated: Option<String>,
+    pub has_history: Option<bool>,
+    pub has_statistics: Option<bool>,
+    pub type_: DeviceType,
+    pub present: Option<bool>,
+    pub rechargeable: Option<bool>,
+    pub state: Option<BatteryState>,
+    pub warning_level: Option<WarningLevel>,
+    pub battery_level: Option<BatteryLevel>,
+    /// In Wh
+    pub energy: Option<f64>,
+    /// In Wh
+    pub energy_empty: Option<f64>,
+    /// In Wh
+    pub energy_full: Option<f64>,
+    /// In Wh
+    pub energy_full_design: Option<f64>,
+    /// In V
+    pub voltage_min_design: Option<f64>,
+    /// In V
+    pub voltage_max_design: Option<f64>,
+    pub capacity_level: Option<CapacityLevel>,
+    /// In W
+    pub energy_rate: Option<f64>,
+    /// In V
+    pub voltage: Option<f64>,
+    /// `Some(None)` is printed as `N/A`
+    pub charge_cycles: Option<Option<i32>>,
+    /// In lx
+    pub luminosity: Option<f64>,
+    pub time_to_full: Option<IntSeconds>,
+    pub time_to_empty: Option<IntSeconds>,
+    /// UPower's percentage is a float, so this is too
+    pub percentage: Option<f64>,
+    /// upower adds `(should be ignored)` to the percentage when the device reports a coarse battery level instead
+    pub percentage_ignored: bool,
+    /// In degrees C
+    pub temperature: Option<f64>,
+    pub capacity: Option<f64>,
+    pub technology: Option<Technology>,
+    pub charge_start_threshold: Option<f64>,
+    pub charge_end_threshold: Option<f64>,
+    pub charge_threshold_enabled: Option<bool>,
+    pub charge_threshold_supported: Option<bool>,
+    pub online: Option<bool>,
+    pub icon_name: Option<String>,
+    /// Device lines that this parser doesn't know about, printed after the ones it does
+    pub unknown: Vec<(String, String)>,
+    /// Each `History (kind):` section, in order
+    pub history: Vec<(String, Vec<HistoryItem>)>,
+}
+impl DeviceText {
+    /// Whether this is the composite display device, which upower prints without some of the lines
+    pub fn is_display_device(&self) -> bool {
+        self.object_path.as_deref() == Some(DISPLAY_DEVICE_PATH)
+    }
+
+    /// Convert to a typed snapshot. Anything that wasn't printed gets its default value.
+    pub fn to_details(&self) -> DeviceDetails {
+        DeviceDetails {
+            native_path: self.native_path.clone().unwrap_or_default(),
+            vendor: self.vendor.clone().unwrap_or_default(),
+            model: self.model.clone().unwrap_or_default(),
+            serial: self.serial.clone().unwrap_or_default(),
+            update_time: 0,
+            type_: self.type_,
+            power_supply: self.power_supply.unwrap_or_default(),
+            online: self.online.unwrap_or_default(),
+            energy: self.energy.unwrap_or_default(),
+            energy_empty: self.energy_empty.unwrap_or_default(),
+            energy_full: self.energy_full.unwrap_or_default(),
+            energy_full_design: self.energy_full_design.unwrap_or_default(),
+            energy_rate: self.energy_rate.unwrap_or_default(),
+            voltage: self.voltage.unwrap_or_default(),
+            charge_cycles: self.charge_cycles.flatten().unwrap_or(-1),
+            luminosity: self.luminosity.unwrap_or_default(),
+            time_to_empty: self.time_to_empty.unwrap_or_default(),
+            time_to_full: self.time_to_full.unwrap_or_default(),
+            percentage: self
+                .percentage
+                .and_then(Percentage::from_f64)
+                .unwrap_or_default(),
+            temperature: self.temperature.unwrap_or_default(),
+            is_present: self.present.unwrap_or_default(),
+            state: self.state.unwrap_or_default(),
+            is_rechargeable: self.rechargeable.unwrap_or_default(),
+            capacity: self.capacity.unwrap_or_default(),
+            technology: self.technology.unwrap_or_default(),
+            warning_level: self.warning_level.unwrap_or_default(),
+            battery_level: self.battery_level.unwrap_or(BatteryLevel::None),
+            icon_name: self.icon_name.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Fill in the lines that upower would print for a device with these details.
+    ///
+    /// The `updated` line is left out, because it depends on the local time zone and the current time.
+    pub fn from_details(object_path: Option<String>, details: &DeviceDetails) -> Self {
+        use DeviceType as K;
+        let kind = details.type_;
+        let has_state = matches!(kind, K::Battery | K::Mouse | K::Keyboard | K::Ups);
+
+        let mut me = Self {
+            object_path,
+            native_path: Some(details.native_path.clone()),
+            vendor: Some(details.vendor.clone()).filter(|s| !s.is_empty()),
+            model: Some(details.model.clone()).filter(|s| !s.is_empty()),
+            serial: Some(details.serial.clone()).filter(|s| !s.is_empty()),
+            power_supply: Some(details.power_supply),
+            has_history: Some(false),
+            has_statistics: Some(false),
+            type_: kind,
+            present: has_state.then_some(details.is_present),
+            rechargeable: (!matches!(kind, K::LinePower | K::Unknown))
+                .then_some(details.is_rechargeable),
+            state: has_state.then_some(details.state),
+            warning_level: Some(details.warning_level),
+            battery_level: (!matches!(details.battery_level, BatteryLevel::None))
+                .then_some(details.battery_level),
+            ..Default::default()
+        };
+
+        if kind == K::Battery {
+            me.energy = Some(details.energy);
+            me.energy_empty = Some(details.energy_empty);
+            me.energy_full = Some(details.energy_full);
+            me.energy_full_design = Some(details.energy_full_design);
+            me.charge_cycles = Some(Some(details.charge_cycles).filter(|c| *c > 0));
+            me.temperature = Some(details.temperature).filter(|t| *t > 0.0);
+            me.capacity = Some(details.capacity).filter(|c| *c > 0.0);
+            me.technology = Some(details.technology).filter(|t| *t != Technology::Unknown);
+        }
+        if matches!(kind, K::Battery | K::Monitor) {
+            me.energy_rate = Some(details.energy_rate);
+        }
+        if matches!(kind, K::Battery | K::Ups | K::Monitor) {
+            me.voltage = Some(details.voltage).filter(|v| *v > 0.0);
+        }
+        if kind == K::Keyboard {
+            me.luminosity = Some(details.luminosity).filter(|l| *l > 0.0);
+        }
+        if matches!(kind, K::Battery | K::Ups) {
+            me.time_to_full = Some(details.time_to_full).filter(|t| !t.get().is_zero());
+            me.time_to_empty = Some(details.time_to_empty).filter(|t| !t.get().is_zero());
+        }
+        if !matches!(kind, K::LinePower | K::Unknown) {
+            me.percentage = Some(details.percentage.get() as f64);
+            me.percentage_ignored = !matches!(details.battery_level, BatteryLevel::None);
+        }
+        if kind == K::LinePower {
+            me.online = Some(details.online);
+        }
+        me.icon_name = Some(details.icon_name.clone()).filter(|s| !s.is_empty());
+
+        me
+    }
+
+    /// Fill in the lines that upower would print for the display device
+    pub fn from_display_details(details: &DisplayDeviceDetails) -> Self {
+        let has_state = matches!(details.type_, DeviceType::Battery | DeviceType::Ups);
+        let is_battery = details.type_ == DeviceType::Battery;
+
+        Self {
+            object_path: Some(DISPLAY_DEVICE_PATH.to_owned()),
+            type_: details.type_,
+            present: has_state.then_some(details.is_present),
+            state: has_state.then_some(details.state),
+            warning_level: Some(details.warning_level),
+            energy: is_battery.then_some(details.energy),
+            energy_full: is_battery.then_some(details.energy_full),
+            energy_rate: is_battery.then_some(details.energy_rate),
+            time_to_full: Some(details.time_to_full).filter(|t| has_state && !t.get().is_zero()),
+            time_to_empty: Some(details.time_to_empty).filter(|t| has_state && !t.get().is_zero()),
+            percentage: (details.type_ != DeviceType::Unknown)
+                .then_some(details.percentage.get() as f64),
+            icon_name: Some(details.icon_name.clone()).filter(|s| !s.is_empty()),
+            ..Default::default()
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), DumpParseError> {
+        let invalid = || DumpParseError::InvalidValue {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+        let string = || Some(value.to_owned());
+        let boolean = || parse_bool(value).ok_or_else(invalid);
+        let number = |unit: &str| {
+            value
+                .strip_suffix(unit)
+                .unwrap_or(value)
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| invalid())
+        };
+        fn named<T: UPowerName>(value: &str) -> Option<T> {
+            T::from_upower_name(value)
+        }
+
+        match key {
+            "native-path" => self.native_path = string(),
+            "vendor" => self.vendor = string(),
+            "model" => self.model = string(),
+            "serial" => self.serial = string(),
+            "power supply" => self.power_supply = Some(boolean()?),
+            "updated" => self.updated = string(),
+            "has history" => self.has_history = Some(boolean()?),
+            "has statistics" => self.has_statistics = Some(boolean()?),
+            "present" => self.present = Some(boolean()?),
+            "rechargeable" => self.rechargeable = Some(boolean()?),
+            "state" => self.state = Some(named(value).ok_or_else(invalid)?),
+            "warning-level" => self.warning_level = Some(named(value).ok_or_else(invalid)?),
+            "battery-level" => self.battery_level = Some(named(value).ok_or_else(invalid)?),
+            "energy" => self.energy = Some(number("Wh")?),
+            "energy-empty" => self.energy_empty = Some(number("Wh")?),
+            "energy-full" => self.energy_full = Some(number("Wh")?),
+            "energy-full-design" => self.energy_full_design = Some(number("Wh")?),
+            "voltage-min-design" => self.voltage_min_design = Some(number("V")?),
+            "voltage-max-design" => self.voltage_max_design = Some(number("V")?),
+            "capacity-level" => self.capacity_level = Some(named(value).ok_or_else(invalid)?),
+            "energy-rate" => self.energy_rate = Some(number("W")?),
+            "voltage" => self.voltage = Some(number("V")?),
+            "charge-cycles" => {
+                self.charge_cycles = Some(match value {
+                    "N/A" => None,
+                    _ => Some(value.parse().map_err(|_| invalid())?),
+                })
+            }
+            "luminosity" => self.luminosity = Some(number("lx")?),
+            "time to full" => self.time_to_full = Some(parse_time(value).ok_or_else(invalid)?),
+            "time to empty" => self.time_to_empty = Some(parse_time(value).ok_or_else(invalid)?),
+            "percentage" => {
+                let (value, ignored) = match value.strip_suffix(" (should be ignored)") {
+                    Some(v) => (v, true),
+                    None => (value, false),
+                };
+                self.percentage = Some(
+                    value
+                        .strip_suffix('%')
+                        .and_then(|v| v.parse().ok())
+                        .ok_or_else(invalid)?,
+                );
+                self.percentage_ignored = ignored;
+            }
+            "temperature" => self.temperature = Some(number("degrees C")?),
+            "capacity" => self.capacity = Some(number("%")?),
+            "technology" => self.technology = Some(named(value).ok_or_else(invalid)?),
+            "charge-start-threshold" => self.charge_start_threshold = Some(number("%")?),
+            "charge-end-threshold" => self.charge_end_threshold = Some(number("%")?),
+            "charge-threshold-enabled" => self.charge_threshold_enabled = Some(boolean()?),
+            "charge-threshold-supported" => self.charge_threshold_supported = Some(boolean()?),
+            "online" => self.online = Some(boolean()?),
+            "icon-name" => {
+                self.icon_name = Some(
+                    value
+                        .strip_prefix('\'')
+                        .and_then(|v| v.strip_suffix('\''))
+                        .unwrap_or(value)
+                        .to_owned(),
+                )
+            }
+            _ => {
+                debug!("Unknown upower dump key: {}", key);
+                self.unknown.push((key.to_owned(), value.to_owned()));
+            }
+        }
+
+        Ok(())
+    }
+}
+impl fmt::Display for DeviceText {
+    /// Render exactly like `up_device_to_text`, plus the `Device:` line if there is an object path.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.object_path {
+            writeln!(f, "Device: {path}")?;
+        }
+
+        // Keys that are too long for the column still get a space, like `charge-threshold-supported: yes`
+        let top = |f: &mut fmt::Formatter<'_>, key: &str, value: &dyn fmt::Display| {
+            writeln!(f, "  {:<21} {}", format!("{key}:"), value)
+        };
+        let prop = |f: &mut fmt::Formatter<'_>, key: &str, value: &dyn fmt::Display| {
+            writeln!(f, "    {:<20} {}", format!("{key}:"), value)
+        };
+
+        if let Some(v) = &self.native_path {
+            top(f, "native-path", v)?;
+        }
+        if let Some(v) = &self.vendor {
+            top(f, "vendor", v)?;
+        }
+        if let Some(v) = &self.model {
+            top(f, "model", v)?;
+        }
+        if let Some(v) = &self.serial {
+            top(f, "serial", v)?;
+        }
+        if let Some(v) = self.power_supply {
+            top(f, "power supply", &yes_no(v))?;
+        }
+        if let Some(v) = &self.updated {
+            top(f, "updated", v)?;
+        }
+        if let Some(v) = self.has_history {
+            top(f, "has history", &yes_no(v))?;
+        }
+        if let Some(v) = self.has_statistics {
+            top(f, "has statistics", &yes_no(v))?;
+        }
+        writeln!(f, "  {}", self.type_.upower_name())?;
+
+        if let Some(v) = self.present {
+            prop(f, "present", &yes_no(v))?;
+        }
+        if let Some(v) = self.rechargeable {
+            prop(f, "rechargeable", &yes_no(v))?;
+        }
+        if let Some(v) = self.state {
+            prop(f, "state", &v.upower_name())?;
+        }
+        if let Some(v) = self.warning_level {
+            prop(f, "warning-level", &v.upower_name())?;
+        }
+        if let Some(v) = self.battery_level {
+            prop(f, "battery-level", &v.upower_name())?;
+        }
+        if let Some(v) = self.energy {
+            prop(f, "energy", &format_args!("{} Wh", Float(v)))?;
+        }
+        if let Some(v) = self.energy_empty {
+            prop(f, "energy-empty", &format_args!("{} Wh", Float(v)))?;
+        }
+        if let Some(v) = self.energy_full {
+            prop(f, "energy-full", &format_args!("{} Wh", Float(v)))?;
+        }
+        if let Some(v) = self.energy_full_design {
+            prop(f, "energy-full-design", &format_args!("{} Wh", Float(v)))?;
+        }
+        if let Some(v) = self.voltage_min_design {
+            prop(f, "voltage-min-design", &format_args!("{} V", Float(v)))?;
+        }
+        if let Some(v) = self.voltage_max_design {
+            prop(f, "voltage-max-design", &format_args!("{} V", Float(v)))?;
+        }
+        if let Some(v) = self.capacity_level {
+            prop(f, "capacity-level", &v.upower_name())?;
+        }
+        if let Some(v) = self.energy_rate {
+            prop(f, "energy-rate", &format_args!("{} W", Float(v)))?;
+        }
+        if let Some(v) = self.voltage {
+            prop(f, "voltage", &format_args!("{} V", Float(v)))?;
+        }
+        match self.charge_cycles {
+            Some(Some(v)) => prop(f, "charge-cycles", &v)?,
+            Some(None) => prop(f, "charge-cycles", &"N/A")?,
+            None => {}
+        }
+        if let Some(v) = self.luminosity {
+            prop(f, "luminosity", &format_args!("{} lx", Float(v)))?;
+        }
+        if let Some(v) = self.time_to_full {
+            prop(f, "time to full", &Time(v))?;
+        }
+        if let Some(v) = self.time_to_empty {
+            prop(f, "time to empty", &Time(v))?;
+        }
+        if let Some(v) = self.percentage {
+            match self.percentage_ignored {
+                true => prop(
+                    f,
+                    "percentage",
+                    &format_args!("{}% (should be ignored)", Float(v)),
+                )?,
+                false => prop(f, "percentage", &format_args!("{}%", Float(v)))?,
+            }
+        }
+        if let Some(v) = self.temperature {
+            prop(f, "temperature", &format_args!("{} degrees C", Float(v)))?;
+        }
+        if let Some(v) = self.capacity {
+            prop(f, "capacity", &format_args!("{}%", Float(v)))?;
+        }
+        if let Some(v) = self.technology {
+            prop(f, "technology", &v.upower_name())?;
+        }
+        if let Some(v) = self.charge_start_threshold {
+            prop(f, "charge-start-threshold", &format_args!("{}%", Float(v)))?;
+        }
+        if let Some(v) = self.charge_end_threshold {
+            prop(f, "charge-end-threshold", &format_args!("{}%", Float(v)))?;
+        }
+        if let Some(v) = self.charge_threshold_enabled {
+            prop(f, "charge-threshold-enabled", &yes_no(v))?;
+        }
+        if let Some(v) = self.charge_threshold_supported {
+            prop(f, "charge-threshold-supported", &yes_no(v))?;
+        }
+        if let Some(v) = self.online {
+            prop(f, "online", &yes_no(v))?;
+        }
+        for (key, value) in self.unknown.iter() {
+            prop(f, key, value)?;
+        }
+        // Yes, this one really is a column short
+        if let Some(v) = &self.icon_name {
+            writeln!(f, "    icon-name:          '{v}'")?;
+        }
+
+        for (kind, lines) in self.history.iter() {
+            writeln!(f, "  History ({kind}):")?;
+            for item in lines {
+                writeln!(f, "    {item}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The `Daemon:` section at the end of `upower -d`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DaemonText {
+    pub daemon_version: Option<String>,
+    pub on_battery: Option<bool>,
+    pub lid_is_closed: Option<bool>,
+    pub lid_is_present: Option<bool>,
+    pub critical_action: Option<CriticalAction>,
+}
+impl DaemonText {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), DumpParseError> {
+        let invalid = || DumpParseError::InvalidValue {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+
+        match key {
+            "daemon-version" => self.daemon_version = Some(value.to_owned()),
+            "on-battery" => self.on_battery = Some(parse_bool(value).ok_or_else(invalid)?),
+            "lid-is-closed" => self.lid_is_closed = Some(parse_bool(value).ok_or_else(invalid)?),
+            "lid-is-present" => self.lid_is_present = Some(parse_bool(value).ok_or_else(invalid)?),
+            "critical-action" => {
+                self.critical_action =
+                    Some(CriticalAction::from_upower_name(value).ok_or_else(invalid)?)
+            }
+            _ => debug!("Unknown upower daemon key: {}", key),
+        }
+
+        Ok(())
+    }
+}
+impl fmt::Display for DaemonText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = |f: &mut fmt::Formatter<'_>, key: &str, value: &dyn fmt::Display| {
+            writeln!(f, "  {:<16} {}", format!("{key}:"), value)
+        };
+
+        writeln!(f, "Daemon:")?;
+        if let Some(v) = &self.daemon_version {
+            line(f, "daemon-version", v)?;
+        }
+        if let Some(v) = self.on_battery {
+            line(f, "on-battery", &yes_no(v))?;
+        }
+        if let Some(v) = self.lid_is_closed {
+            line(f, "lid-is-closed", &yes_no(v))?;
+        }
+        if let Some(v) = self.lid_is_present {
+            line(f, "lid-is-present", &yes_no(v))?;
+        }
+        if let Some(v) = self.critical_action {
+            line(f, "critical-action", &v.upower_name())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A whole `upower -d` dump, or a single `upower -i` device
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dump {
+    pub devices: Vec<DeviceText>,
+    pub daemon: Option<DaemonText>,
+}
+impl Dump {
+    pub fn parse(text: &str) -> Result<Self, DumpParseError> {
+        enum Section {
+            None,
+            Device,
+            History,
+            Daemon,
+        }
+
+        let mut me = Self::default();
+        let mut section = Section::None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            if let Some(path) = line.strip_prefix("Device: ") {
+                me.devices.push(DeviceText {
+                    object_path: Some(path.trim().to_owned()),
+                    ..Default::default()
+                });
+                section = Section::Device;
+                continue;
+            }
+            if line == "Daemon:" {
+                me.daemon = Some(DaemonText::default());
+                section = Section::Daemon;
+                continue;
+            }
+
+            // `upower -i` doesn't print a `Device:` line
+            if matches!(section, Section::None) {
+                me.devices.push(DeviceText::default());
+                section = Section::Device;
+            }
+
+            if let Section::Daemon = section {
+                let (key, value) = trimmed
+                    .split_once(':')
+                    .ok_or(DumpParseError::Syntax(line_number))?;
+                me.daemon
+                    .get_or_insert_with(Default::default)
+                    .set(key.trim(), value.trim())?;
+                continue;
+            }
+
+            let device = me
+                .devices
+                .last_mut()
+                .ok_or(DumpParseError::Syntax(line_number))?;
+
+            if let Some(kind) = trimmed
+                .strip_prefix("History (")
+                .and_then(|k| k.strip_suffix("):"))
+            {
+                device.history.push((kind.to_owned(), Vec::new()));
+                section = Section::History;
+                continue;
+            }
+
+            if let Section::History = section {
+                let item = trimmed
+                    .parse::<HistoryItem>()
+                    .map_err(|_| DumpParseError::Syntax(line_number))?;
+                if let Some((_, lines)) = device.history.last_mut() {
+                    lines.push(item);
+                }
+                continue;
+            }
+
+            match trimmed.split_once(':') {
+                Some((key, value)) => device.set(key.trim(), value.trim())?,
+                // The only line without a colon is the device kind
+                None => {
+                    device.type_ = DeviceType::from_upower_name(trimmed)
+                        .ok_or(DumpParseError::Syntax(line_number))?
+                }
+            }
+        }
+
+        Ok(me)
+    }
+}
+impl fmt::Display for Dump {
+    /// Devices are separated by blank lines like `upower -d` does
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for device in self.devices.iter() {
+            writeln!(f, "{device}")?;
+        }
+        if let Some(daemon) = &self.daemon {
+            write!(f, "{daemon}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DumpParseError {
+    /// The line at this (1-based) line number could not be understood
+    Syntax(usize),
+    InvalidValue {
+        key: String,
+        value: String,
+    },
+}
+impl fmt::Display for DumpParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(line) => write!(f, "Syntax error in upower dump on line {line}"),
+            Self::InvalidValue { key, value } => write!(f, "Invalid value for {key}: {value}"),
+        }
+    }
+}
+impl std::error::Error for DumpParseError {}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+#[inline]
+const fn yes_no(value: bool) -> &'static str {
+    match value {
+        true => "yes",
+        false => "no",
+    }
+}
+
+/// Parse the output of `up_device_to_text_time_to_string`
+fn parse_time(value: &str) -> Option<IntSeconds> {
+    let (number, unit) = value.split_once(' ')?;
+    let number = number.parse::<f64>().ok()?;
+    let multiplier = match unit {
+        "seconds" => 1.0,
+        "minutes" => 60.0,
+        "hours" => 3600.0,
+        "days" => 86400.0,
+        _ => return None,
+    };
+    Some(IntSeconds::new_from_signed(
+        (number * multiplier).round() as i64
+    ))
+}
+
+/// Formats a time like `up_device_to_text_time_to_string`
+struct Time(IntSeconds);
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // upower does this math with a gfloat
+        let mut value = self.0.as_signed_secs() as f32;
+        if value < 0.0 {
+            return f.write_str("unknown");
+        }
+        if value < 60.0 {
+            return write!(f, "{value:.0} seconds");
+        }
+        value /= 60.0;
+        if value < 60.0 {
+            return write!(f, "{value:.1} minutes");
+        }
+        value /= 60.0;
+        if value < 24.0 {
+            return write!(f, "{value:.1} hours");
+        }
+        value /= 24.0;
+        write!(f, "{value:.1} days")
+    }
+}
+
+/// Formats a float like C's `%g`
+struct Float(f64);
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const PRECISION: i32 = 6;
+        let value = self.0;
+
+        if value == 0.0 {
+            return f.write_str("0");
+        }
+        if !value.is_finite() {
+            return match (value.is_nan(), value.is_sign_negative()) {
+                (true, _) => f.write_str("nan"),
+                (false, true) => f.write_str("-inf"),
+                (false, false) => f.write_str("inf"),
+            };
+        }
+
+        // Round to the precision first, because that can bump the exponent (999999.5 -> 1e+06)
+        let scientific = format!("{:.*e}", (PRECISION - 1) as usize, value);
+        let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+        let exponent = exponent.parse::<i32>().unwrap_or_default();
+
+        let mut out = String::new();
+        if (-4..PRECISION).contains(&exponent) {
+            let decimals = (PRECISION - 1 - exponent).max(0) as usize;
+            out.push_str(strip_zeros(&format!("{value:.decimals$}")));
+        } else {
+            out.push_str(strip_zeros(mantissa));
+            let sign = if exponent < 0 { '-' } else { '+' };
+            write!(out, "e{sign}{:02}", exponent.abs())?;
+        }
+
+        f.write_str(&out)
+    }
+}
+
+fn strip_zeros(number: &str) -> &str {
+    match number.contains('.') {
+        true => number.trim_end_matches('0').trim_end_matches('.'),
+        false => number,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, ::std::path::Path};
+
+    fn fixture() -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/upower-d.txt");
+        ::std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn round_trips_byte_for_byte() {
+        let text = fixture();
+        let dump = Dump::parse(&text).unwrap();
+        assert_eq!(dump.to_string(), text);
+        assert_eq!(Dump::parse(&dump.to_string()), Ok(dump));
+    }
+
+    #[test]
+    fn parses_a_dump() {
+        let dump = Dump::parse(&fixture()).unwrap();
+        let types: Vec<_> = dump.devices.iter().map(|d| d.type_).collect();
+        assert_eq!(
+            types,
+            [
+                DeviceType::LinePower,
+                DeviceType::Battery,
+                DeviceType::Keyboard,
+                DeviceType::Battery
+            ]
+        );
+
+        let battery = &dump.devices[1];
+        assert_eq!(battery.state, Some(BatteryState::Discharging));
+        assert_eq!(battery.capacity_level, Some(CapacityLevel::Normal));
+        assert_eq!(battery.technology, Some(Technology::LithiumPolymer));
+        assert_eq!(battery.energy_rate, Some(8.743));
+        assert_eq!(battery.charge_cycles, Some(Some(212)));
+        assert_eq!(
+            battery.time_to_empty,
+            Some(IntSeconds::new_from_unsigned(17640))
+        );
+        assert_eq!(battery.history.len(), 2);
+        assert_eq!(battery.history[0].1.len(), 3);
+
+        let keyboard = &dump.devices[2];
+        assert_eq!(keyboard.battery_level, Some(BatteryLevel::High));
+        assert!(keyboard.percentage_ignored);
+        assert!(dump.devices[3].is_display_device());
+        assert_eq!(dump.devices[3].charge_cycles, Some(None));
+
+        let daemon = dump.daemon.unwrap();
+        assert_eq!(daemon.daemon_version.as_deref(), Some("1.90.6"));
+        assert_eq!(daemon.critical_action, Some(CriticalAction::HybridSleep));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert_eq!(
+            Dump::parse("  not-a-device-kind\n"),
+            Err(DumpParseError::Syntax(1))
+        );
+        assert_eq!(
+            Dump::parse("  battery\n    present:             maybe\n"),
+            Err(DumpParseError::InvalidValue {
+                key: "present".to_owned(),
+                value: "maybe".to_owned(),
+            })
+        );
+    }
+}
@@ -0,0 +1,428 @@
+//! Client-side time remaining estimates.
+//!
+//! UPower's `TimeToEmpty` and `TimeToFull` jump around a lot, and some firmware reports 0 for minutes after unplugging.
+//! This keeps a sliding window of samples and fits a line through them, falling back to a smoothed energy rate
+//! when there isn't enough history yet.
+use {
+    crate::{
+        types::{BatteryState, IntSeconds, Percentage},
+        xmlgen::{display_device::DeviceProxy, DisplayDeviceDetails},
+    },
+    ::core::time::Duration,
+    ::futures_util::{Stream, StreamExt},
+    ::std::{collections::VecDeque, time::Instant},
+};
+
+/// A single reading from a device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// When this sample was taken, relative to any fixed point in time
+    pub timestamp: Duration,
+    pub energy: f64,
+    pub energy_full: f64,
+    pub energy_rate: f64,
+    pub percentage: Percentage,
+    pub state: BatteryState,
+}
+impl Sample {
+    pub fn from_details(timestamp: Duration, details: &DisplayDeviceDetails) -> Self {
+        Self {
+            timestamp,
+            energy: details.energy,
+            energy_full: details.energy_full,
+            energy_rate: details.energy_rate,
+            percentage: details.percentage,
+            state: details.state,
+        }
+    }
+
+    /// Some devices only report a percentage, so we pretend it's energy in that case.
+    fn level(&self) -> (f64, f64) {
+        match self.energy_full > 0.0 {
+            true => (self.energy, self.energy_full),
+            false => (self.percentage.get() as f64, 100.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EstimatorConfig {
+    /// Samples older than this are forgotten
+    pub window: Duration,
+    /// The maximum number of samples to keep, even if they are inside the window
+    pub max_samples: usize,
+    /// How many samples are needed before the line fit is trusted
+    pub min_samples: usize,
+    /// The smoothing factor for the energy rate, from 0.0 (never changes) to 1.0 (no smoothing)
+    pub alpha: f64,
+}
+impl Default for EstimatorConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(600),
+            max_samples: 120,
+            min_samples: 4,
+            alpha: 0.2,
+        }
+    }
+}
+
+/// What the battery is heading towards
+#[derive(Debug, Copy, Clone, PartialEq, Eq, strum_macros::Display, strum_macros::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum EstimateTarget {
+    Empty,
+    Full,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, strum_macros::Display, strum_macros::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum EstimateMethod {
+    /// A least-squares line fit over the sample window
+    Regression,
+    /// The exponentially weighted moving average of the reported energy rate
+    SmoothedRate,
+}
+
+/// A time remaining estimate, with a confidence interval of roughly two standard deviations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Estimate {
+    pub target: EstimateTarget,
+    pub remaining: IntSeconds,
+    /// The shortest time inside the interval
+    pub lower: IntSeconds,
+    /// The longest time inside the interval, or None if the rate could be zero, so there is no upper bound
+    pub upper: Option<IntSeconds>,
+    pub method: EstimateMethod,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TimeEstimator {
+    config: EstimatorConfig,
+    samples: VecDeque<Sample>,
+    /// The exponentially weighted mean and variance of the reported energy rate
+    smoothed_rate: Option<(f64, f64)>,
+}
+impl TimeEstimator {
+    pub fn new(config: EstimatorConfig) -> Self {
+        Self {
+            config,
+            samples: VecDeque::with_capacity(config.max_samples),
+            smoothed_rate: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.smoothed_rate = None;
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        // Plugging in or unplugging makes all the old history useless
+        if let Some(last) = self.samples.back() {
+            if target_for(last.state) != target_for(sample.state)
+                || sample.timestamp < last.timestamp
+            {
+                self.clear();
+            }
+        }
+
+        while let Some(first) = self.samples.front() {
+            let too_old = sample.timestamp.saturating_sub(first.timestamp) > self.config.window;
+            if !too_old && self.samples.len() < self.config.max_samples {
+                break;
+            }
+            self.samples.pop_front();
+        }
+
+        if sample.energy_rate.abs() > f64::EPSILON {
+            let rate = sample.energy_rate.abs();
+            let alpha = self.config.alpha;
+            self.smoothed_rate = Some(match self.smoothed_rate {
+                Some((mean, variance)) => {
+                    let diff = rate - mean;
+                    let mean = mean + alpha * diff;
+                    let variance = (1.0 - alpha) * (variance + alpha * diff * diff);
+                    (mean, variance)
+                }
+                None => (rate, 0.0),
+            });
+        }
+
+        self.samples.push_back(sample);
+    }
+
+    /// Estimate the time until the battery is empty or full, depending on whether it's charging.
+    ///
+    /// Returns None if the battery isn't charging or discharging, or there is no data yet.
+    pub fn estimate(&self) -> Option<Estimate> {
+        let last = self.samples.back()?;
+        let target = target_for(last.state)?;
+
+        let (level, full) = last.level();
+        let distance = match target {
+            EstimateTarget::Empty => level,
+            EstimateTarget::Full => full - level,
+        }
+        .max(0.0);
+
+        // Rates are in level units per hour
+        let (rate, spread, method) = match self.fit() {
+            Some((rate, spread)) => (rate, spread, EstimateMethod::Regression),
+            None => {
+                let (mean, variance) = self.smoothed_rate?;
+                // energy_rate is always in W, which doesn't make sense with a fake percentage level
+                if last.energy_full <= 0.0 {
+                    return None;
+                }
+                (mean, variance.sqrt(), EstimateMethod::SmoothedRate)
+            }
+        };
+
+        if rate <= f64::EPSILON {
+            return None;
+        }
+
+        let hours_to_seconds = |rate: f64| {
+            (rate > f64::EPSILON)
+                .then(|| IntSeconds::new_from_unsigned((distance / rate * 3600.0).round() as u64))
+        };
+
+        Some(Estimate {
+            target,
+            remaining: hours_to_seconds(rate)?,
+            lower: hours_to_seconds(rate + 2.0 * spread)?,
+            upper: hours_to_seconds(rate - 2.0 * spread),
+            method,
+        })
+    }
+
+    /// Use the daemon's time estimates, unless they are zero, in which case they get replaced with ours.
+    ///
+    /// Returns `(time_to_empty, time_to_full)`.
+    pub fn fill_in(&self, details: &DisplayDeviceDetails) -> (IntSeconds, IntSeconds) {
+        let mut time_to_empty = details.time_to_empty;
+        let mut time_to_full = details.time_to_full;
+
+        if let Some(estimate) = self.estimate() {
+            match estimate.target {
+                EstimateTarget::Empty if time_to_empty.get().is_zero() => {
+                    time_to_empty = estimate.remaining
+                }
+                EstimateTarget::Full if time_to_full.get().is_zero() => {
+                    time_to_full = estimate.remaining
+                }
+                _ => {}
+            }
+        }
+
+        (time_to_empty, time_to_full)
+    }
+
+    /// Fit a line through the level over time. Returns the absolute rate of change per hour and its standard error.
+    fn fit(&self) -> Option<(f64, f64)> {
+        let n = self.samples.len();
+        if n < self.config.min_samples.max(3) {
+            return None;
+        }
+
+        let start = self.samples.front()?.timestamp;
+        let points = || {
+            self.samples.iter().map(move |s| {
+                let hours = (s.timestamp - start).as_secs_f64() / 3600.0;
+                (hours, s.level().0)
+            })
+        };
+
+        let count = n as f64;
+        let (sum_x, sum_y) = points().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+        let (mean_x, mean_y) = (sum_x / count, sum_y / count);
+
+        let (sxx, sxy) = points().fold((0.0, 0.0), |(sxx, sxy), (x, y)| {
+            let dx = x - mean_x;
+            (sxx + dx * dx, sxy + dx * (y - mean_y))
+        });
+        if sxx <= f64::EPSILON {
+            return None;
+        }
+
+        let slope = sxy / sxx;
+        let intercept = mean_y - slope * mean_x;
+
+        let residuals: f64 = points()
+            .map(|(x, y)| {
+                let r = y - (intercept + slope * x);
+                r * r
+            })
+            .sum();
+        let standard_error = (residuals / (count - 2.0) / sxx).sqrt();
+
+        // A slope in the wrong direction means the window is not useful yet
+        let rate = match target_for(self.samples.back()?.state)? {
+            EstimateTarget::Empty => -slope,
+            EstimateTarget::Full => slope,
+        };
+
+        match rate > f64::EPSILON {
+            true => Some((rate, standard_error)),
+            false => None,
+        }
+    }
+}
+
+fn target_for(state: BatteryState) -> Option<EstimateTarget> {
+    match state {
+        BatteryState::Discharging | BatteryState::PendingDischarge => Some(EstimateTarget::Empty),
+        BatteryState::Charging | BatteryState::PendingCharge => Some(EstimateTarget::Full),
+        _ => None,
+    }
+}
+
+/// Watch a device, feeding every update into a new estimator.
+///
+/// The daemon's time estimates in the yielded details are filled in with ours when they are zero.
+pub async fn watch<'a>(
+    proxy: &'a DeviceProxy<'a>,
+    config: EstimatorConfig,
+) -> impl Stream<Item = (DisplayDeviceDetails, Option<Estimate>)> + 'a {
+    let start = Instant::now();
+    let mut estimator = TimeEstimator::new(config);
+
+    DisplayDeviceDetails::watch(proxy)
+        .await
+        .map(move |mut details| {
+            estimator.push(Sample::from_details(start.elapsed(), &details));
+            (details.time_to_empty, details.time_to_full) = estimator.fill_in(&details);
+            (details, estimator.estimate())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(minutes: u64, energy: f64, energy_rate: f64, state: BatteryState) -> Sample {
+        Sample {
+            timestamp: Duration::from_secs(minutes * 60),
+            energy,
+            energy_full: 50.0,
+            energy_rate,
+            percentage: Percentage::from_f64(energy * 2.0).unwrap_or(Percentage::MAX),
+            state,
+        }
+    }
+
+    fn seconds(s: u64) -> IntSeconds {
+        IntSeconds::new_from_unsigned(s)
+    }
+
+    #[test]
+    fn fits_a_linear_discharge() {
+        let mut estimator = TimeEstimator::default();
+        // 10 Wh an hour, so a sixth of a Wh a minute
+        for minute in 0..5 {
+            estimator.push(sample(
+                minute,
+                40.0 - minute as f64 / 6.0,
+                10.0,
+                BatteryState::Discharging,
+            ));
+        }
+
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(estimate.method, EstimateMethod::Regression);
+        assert_eq!(estimate.target, EstimateTarget::Empty);
+        // 39.33 Wh left at 10 W
+        assert_eq!(estimate.remaining, seconds(14160));
+        // A perfect line leaves no doubt
+        assert_eq!(estimate.lower, estimate.remaining);
+        assert_eq!(estimate.upper, Some(estimate.remaining));
+
+        // With some noise the interval widens around the estimate
+        estimator.push(sample(5, 39.3, 10.0, BatteryState::Discharging));
+        let estimate = estimator.estimate().unwrap();
+        assert!(estimate.lower.get() < estimate.remaining.get());
+        assert!(estimate.upper.unwrap().get() > estimate.remaining.get());
+    }
+
+    #[test]
+    fn uses_the_smoothed_rate_before_min_samples() {
+        let mut estimator = TimeEstimator::default();
+        estimator.push(sample(0, 26.0, 10.0, BatteryState::Discharging));
+        estimator.push(sample(1, 26.0, 12.0, BatteryState::Discharging));
+
+        // mean = 10 + 0.2 * 2 = 10.4 W, variance = 0.8 * 0.2 * 4 = 0.64, so the spread is 0.8 W
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(estimate.method, EstimateMethod::SmoothedRate);
+        assert_eq!(estimate.remaining, seconds(9000));
+        assert_eq!(estimate.lower, seconds(7800));
+        assert_eq!(estimate.upper, Some(seconds(10636)));
+
+        // A fake level from the percentage can't be divided by a rate in W
+        let mut estimator = TimeEstimator::default();
+        estimator.push(Sample {
+            energy_full: 0.0,
+            ..sample(0, 26.0, 10.0, BatteryState::Discharging)
+        });
+        assert_eq!(estimator.estimate(), None);
+    }
+
+    #[test]
+    fn no_upper_bound_when_the_rate_could_be_zero() {
+        let mut estimator = TimeEstimator::default();
+        estimator.push(sample(0, 26.0, 1.0, BatteryState::Discharging));
+        estimator.push(sample(1, 26.0, 100.0, BatteryState::Discharging));
+
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(estimate.upper, None);
+        assert!(estimate.lower.get() < estimate.remaining.get());
+    }
+
+    #[test]
+    fn plugging_in_clears_the_history() {
+        let mut estimator = TimeEstimator::default();
+        for minute in 0..5 {
+            estimator.push(sample(
+                minute,
+                40.0 - minute as f64,
+                60.0,
+                BatteryState::Discharging,
+            ));
+        }
+        estimator.push(sample(5, 35.0, 15.0, BatteryState::Charging));
+
+        // Only the charging sample is left, and the old rate is forgotten
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(estimate.target, EstimateTarget::Full);
+        assert_eq!(estimate.method, EstimateMethod::SmoothedRate);
+        assert_eq!(estimate.remaining, seconds(3600));
+
+        estimator.push(sample(6, 35.0, 0.0, BatteryState::FullyCharged));
+        assert_eq!(estimator.estimate(), None);
+    }
+
+    #[test]
+    fn fills_in_only_zero_times() {
+        let mut estimator = TimeEstimator::default();
+        estimator.push(sample(0, 25.0, 10.0, BatteryState::Discharging));
+
+        let details = DisplayDeviceDetails {
+            state: BatteryState::Discharging,
+            time_to_empty: seconds(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            estimator.fill_in(&details),
+            (seconds(100), IntSeconds::default())
+        );
+
+        let details = DisplayDeviceDetails {
+            time_to_empty: IntSeconds::default(),
+            ..details
+        };
+        assert_eq!(
+            estimator.fill_in(&details),
+            (seconds(9000), IntSeconds::default())
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Predict when UPower's critical power action is going to run.
+//!
+//! [`UPowerProxy::get_critical_action`](crate::upower::UPowerProxy::get_critical_action) only tells you *what* will happen.
+//! This combines it with the action threshold from [`UPowerConfig`] and the display device's energy readings to tell you *when*.
+use {
+    crate::{
+        config::UPowerConfig,
+        types::{BatteryState, CriticalAction, IntSeconds},
+        xmlgen::{display_device::DeviceProxy, upower::UPowerProxy, DisplayDeviceDetails},
+    },
+    ::core::fmt,
+};
+
+/// How a [`CriticalActionForecast`] was calculated
+#[derive(Debug, Copy, Clone, PartialEq, Eq, strum_macros::Display, strum_macros::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ForecastMethod {
+    /// From the energy left above the action percentage, divided by the energy rate
+    EnergyRate,
+    /// From the daemon's own `TimeToEmpty`, scaled down to the action percentage
+    TimeToEmpty,
+    /// From the daemon's `TimeToEmpty` minus the configured `TimeAction`
+    TimePolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CriticalActionForecast {
+    /// What will happen
+    pub action: CriticalAction,
+    /// How long until it happens
+    pub time_remaining: IntSeconds,
+    /// How much this should be trusted, from 0.0 to 1.0
+    pub confidence: f64,
+    pub method: ForecastMethod,
+}
+impl CriticalActionForecast {
+    /// Make a forecast from the data you already have.
+    ///
+    /// Returns None if the battery isn't discharging, the action is `Ignore` or `Unknown` so there's nothing to forecast,
+    /// or there isn't enough data to say anything useful.
+    pub fn new(
+        action: CriticalAction,
+        config: &UPowerConfig,
+        details: &DisplayDeviceDetails,
+    ) -> Option<Self> {
+        if details.state != BatteryState::Discharging
+            || matches!(action, CriticalAction::Ignore | CriticalAction::Unknown)
+        {
+            return None;
+        }
+
+        let config = config.effective();
+        let time_to_empty = details.time_to_empty.as_signed_secs();
+
+        let (seconds, method, mut confidence) = if config.use_percentage_for_policy {
+            let action_fraction = config.percentage_action.get() as f64 / 100.0;
+            let target_energy = details.energy_full * action_fraction;
+
+            if details.energy_rate.abs() > f64::EPSILON && details.energy_full > 0.0 {
+                let rate = details.energy_rate.abs();
+                let seconds = (details.energy - target_energy) / rate * 3600.0;
+
+                // If the daemon agrees with us, we can be fairly sure
+                let mut confidence = 0.8;
+                if time_to_empty > 0 {
+                    let our_time_to_empty = details.energy / rate * 3600.0;
+                    let disagreement =
+                        ((our_time_to_empty - time_to_empty as f64) / time_to_empty as f64).abs();
+                    confidence += 0.15 * (1.0 - disagreement.min(1.0));
+                }
+
+                (seconds, ForecastMethod::EnergyRate, confidence)
+            } else if time_to_empty > 0 && details.percentage.get() > 0 {
+                let percentage = details.percentage.get() as f64;
+                let above_action = percentage - config.percentage_action.get() as f64;
+                let seconds = time_to_empty as f64 * (above_action / percentage);
+
+                (seconds, ForecastMethod::TimeToEmpty, 0.5)
+            } else {
+                return None;
+            }
+        } else if time_to_empty > 0 {
+            let seconds = time_to_empty - config.time_action.get().as_secs() as i64;
+            (seconds as f64, ForecastMethod::TimePolicy, 0.7)
+        } else {
+            return None;
+        };
+
+        // Predictions for the far future are less reliable, because usage will change in the meantime.
+        let hours = seconds / 3600.0;
+        if hours > 1.0 {
+            confidence /= hours.sqrt();
+        }
+
+        Some(Self {
+            action,
+            time_remaining: IntSeconds::new_from_unsigned(seconds.max(0.0).round() as u64),
+            confidence: confidence.clamp(0.0, 1.0),
+            method,
+        })
+    }
+
+    /// Request all the data needed for a forecast from the daemon.
+    pub async fn request<'c>(
+        upower: &UPowerProxy<'c>,
+        device: &DeviceProxy<'c>,
+        config: &UPowerConfig,
+    ) -> ::zbus::Result<Option<Self>> {
+        let (action, details) = ::futures_util::join!(
+            upower.get_critical_action(),
+            DisplayDeviceDetails::request_all(device)
+        );
+
+        Ok(Self::new(action?, config, &details.try_resolve()?))
+    }
+
+    /// What the action does, to finish the sentence "the system will ..."
+    const fn phrase(action: CriticalAction) -> &'static str {
+        match action {
+            CriticalAction::HybridSleep => "hybrid-sleep",
+            CriticalAction::Hibernate => "hibernate",
+            CriticalAction::PowerOff => "power off",
+            CriticalAction::Suspend => "suspend",
+            CriticalAction::Ignore => "do nothing",
+            CriticalAction::Unknown => "run its critical power action",
+        }
+    }
+}
+impl fmt::Display for CriticalActionForecast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let minutes = self.time_remaining.get().as_secs() / 60;
+        let hours = minutes / 60;
+
+        write!(f, "the system will {} ", Self::phrase(self.action))?;
+        match (hours, minutes % 60) {
+            (0, 0) => f.write_str("in less than a minute"),
+            (0, 1) => f.write_str("in about 1 minute"),
+            (0, m) => write!(f, "in about {m} minutes"),
+            (1, 0) => f.write_str("in about 1 hour"),
+            (h, 0) => write!(f, "in about {h} hours"),
+            (1, m) => write!(f, "in about 1 hour {m} minutes"),
+            (h, m) => write!(f, "in about {h} hours {m} minutes"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::types::Percentage};
+
+    fn discharging() -> DisplayDeviceDetails {
+        DisplayDeviceDetails {
+            state: BatteryState::Discharging,
+            percentage: Percentage::new_saturating(50),
+            energy: 25.0,
+            energy_full: 50.0,
+            energy_rate: 10.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_forecast_without_an_action() {
+        let config = UPowerConfig::default();
+        for action in [CriticalAction::Ignore, CriticalAction::Unknown] {
+            assert_eq!(
+                CriticalActionForecast::new(action, &config, &discharging()),
+                None
+            );
+        }
+
+        let charging = DisplayDeviceDetails {
+            state: BatteryState::Charging,
+            ..discharging()
+        };
+        assert_eq!(
+            CriticalActionForecast::new(CriticalAction::PowerOff, &config, &charging),
+            None
+        );
+    }
+
+    #[test]
+    fn energy_rate_forecast() {
+        let forecast = CriticalActionForecast::new(
+            CriticalAction::HybridSleep,
+            &UPowerConfig::default(),
+            &discharging(),
+        )
+        .unwrap();
+        assert_eq!(forecast.method, ForecastMethod::EnergyRate);
+        // 25 Wh minus the 2% action level of 50 Wh, at 10 W
+        assert_eq!(forecast.time_remaining.get().as_secs(), 8640);
+    }
+
+    #[test]
+    fn display_uses_phrases() {
+        let forecast = |action, seconds| CriticalActionForecast {
+            action,
+            time_remaining: IntSeconds::new_from_unsigned(seconds),
+            confidence: 1.0,
+            method: ForecastMethod::EnergyRate,
+        };
+
+        for (action, seconds, text) in [
+            (
+                CriticalAction::HybridSleep,
+                8640,
+                "the system will hybrid-sleep in about 2 hours 24 minutes",
+            ),
+            (
+                CriticalAction::Hibernate,
+                3600,
+                "the system will hibernate in about 1 hour",
+            ),
+            (
+                CriticalAction::PowerOff,
+                60,
+                "the system will power off in about 1 minute",
+            ),
+            (
+                CriticalAction::Suspend,
+                30,
+                "the system will suspend in less than a minute",
+            ),
+        ] {
+            assert_eq!(forecast(action, seconds).to_string(), text);
+        }
+    }
+}
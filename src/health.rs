@@ -0,0 +1,331 @@
+//! Battery wear analysis, from the design capacity versus what the battery can actually hold now.
+use {
+    crate::{
+        logging::*,
+        types::{DeviceType, Percentage},
+        xmlgen::{device::PowerDeviceProxy, upower::UPowerProxy},
+    },
+    ::serde::{Deserialize, Serialize},
+};
+
+/// A rough verdict on how worn out a battery is
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Default,
+    strum_macros::Display,
+    strum_macros::AsRefStr,
+    Serialize,
+    Deserialize,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum HealthClass {
+    /// Not enough information to tell
+    #[default]
+    Unknown,
+    Good,
+    Fair,
+    ReplaceSoon,
+}
+
+/// The thresholds used to pick a [`HealthClass`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthThresholds {
+    /// The state of health must be at least this high to be considered good
+    pub good: Percentage,
+    /// The state of health must be at least this high to be considered fair. Anything below should be replaced soon.
+    pub fair: Percentage,
+    /// Batteries that have been through this many cycles can't be considered good anymore, no matter what the capacity says
+    pub fair_cycles: u32,
+}
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            good: Percentage::new_saturating(80),
+            fair: Percentage::new_saturating(60),
+            fair_cycles: 800,
+        }
+    }
+}
+
+/// The raw numbers a [`HealthReport`] is computed from, as read from UPower
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HealthInput {
+    pub native_path: String,
+    pub vendor: String,
+    pub model: String,
+    pub serial: String,
+    /// In Wh
+    pub energy_full: f64,
+    /// In Wh
+    pub energy_full_design: f64,
+    /// UPower's own capacity estimate, from 0 to 100
+    pub capacity: f64,
+    /// -1 if unknown
+    pub charge_cycles: i32,
+    /// In V
+    pub voltage: f64,
+    /// In V, 0 if unknown
+    pub voltage_min_design: f64,
+    /// In V, 0 if unknown
+    pub voltage_max_design: f64,
+}
+impl HealthInput {
+    /// Request everything needed from a device
+    pub async fn request<'c>(proxy: &PowerDeviceProxy<'c>) -> ::zbus::Result<Self> {
+        let (
+            native_path,
+            vendor,
+            model,
+            serial,
+            energy_full,
+            energy_full_design,
+            capacity,
+            charge_cycles,
+            voltage,
+            voltage_min_design,
+            voltage_max_design,
+        ) = ::futures_util::join!(
+            proxy.native_path(),
+            proxy.vendor(),
+            proxy.model(),
+            proxy.serial(),
+            proxy.energy_full(),
+            proxy.energy_full_design(),
+            proxy.capacity(),
+            proxy.charge_cycles(),
+            proxy.voltage(),
+            proxy.voltage_min_design(),
+            proxy.voltage_max_design(),
+        );
+
+        Ok(Self {
+            native_path: native_path?,
+            vendor: vendor?,
+            model: model?,
+            serial: serial?,
+            energy_full: energy_full?,
+            energy_full_design: energy_full_design?,
+            capacity: capacity?,
+            voltage: voltage?,
+            // These are missing from older daemons
+            charge_cycles: charge_cycles.unwrap_or(-1),
+            voltage_min_design: voltage_min_design.unwrap_or_default(),
+            voltage_max_design: voltage_max_design.unwrap_or_default(),
+        })
+    }
+}
+
+/// The health of a single battery
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub native_path: String,
+    pub vendor: String,
+    pub model: String,
+    pub serial: String,
+    /// `EnergyFull / EnergyFullDesign`, if both are known
+    pub state_of_health: Option<Percentage>,
+    /// UPower's own `Capacity`, if it is known
+    pub capacity: Option<Percentage>,
+    pub charge_cycles: Option<u32>,
+    /// Whether the current voltage is inside the design range, if the range is known.
+    ///
+    /// This is only informational, and doesn't affect the [`HealthClass`]: a single reading under load can easily
+    /// dip below the design minimum on a perfectly healthy battery.
+    pub voltage_in_range: Option<bool>,
+    pub energy_full: f64,
+    pub energy_full_design: f64,
+    pub class: HealthClass,
+}
+impl HealthReport {
+    pub fn new(input: HealthInput, thresholds: &HealthThresholds) -> Self {
+        let state_of_health = match input.energy_full > 0.0 && input.energy_full_design > 0.0 {
+            // Some batteries report more than their design capacity when they're new
+            true => Percentage::from_f64(
+                (input.energy_full / input.energy_full_design * 100.0).min(100.0),
+            ),
+            false => None,
+        };
+        let capacity = match input.capacity > 0.0 {
+            true => Percentage::from_f64(input.capacity.min(100.0)),
+            false => None,
+        };
+        let charge_cycles = u32::try_from(input.charge_cycles).ok();
+
+        let voltage_in_range = match input.voltage_min_design > 0.0
+            && input.voltage_max_design > 0.0
+            && input.voltage > 0.0
+        {
+            true => {
+                Some((input.voltage_min_design..=input.voltage_max_design).contains(&input.voltage))
+            }
+            false => None,
+        };
+
+        let class = match state_of_health.or(capacity) {
+            Some(soh) if soh < thresholds.fair => HealthClass::ReplaceSoon,
+            Some(soh)
+                if soh < thresholds.good
+                    || charge_cycles.is_some_and(|c| c >= thresholds.fair_cycles) =>
+            {
+                HealthClass::Fair
+            }
+            Some(_) => HealthClass::Good,
+            None => HealthClass::Unknown,
+        };
+
+        Self {
+            native_path: input.native_path,
+            vendor: input.vendor,
+            model: input.model,
+            serial: input.serial,
+            state_of_health,
+            capacity,
+            charge_cycles,
+            voltage_in_range,
+            energy_full: input.energy_full,
+            energy_full_design: input.energy_full_design,
+            class,
+        }
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> ::serde_json::Result<String> {
+        ::serde_json::to_string(self)
+    }
+
+    /// Make a health report for every battery that UPower knows about.
+    ///
+    /// Batteries that can't be read are logged and left out, so one broken device doesn't hide the others.
+    pub async fn request_all<'c>(
+        connection: &::zbus::Connection,
+        upower: &UPowerProxy<'c>,
+        thresholds: &HealthThresholds,
+    ) -> ::zbus::Result<Vec<Self>> {
+        let mut reports = Vec::new();
+
+        for path in upower.enumerate_devices().await? {
+            let proxy = match PowerDeviceProxy::new(connection, path.clone()).await {
+                Ok(p) => p,
+                Err(e) => {
+                    warning!("Failed to connect to {}: {}", path.as_str(), e);
+                    continue;
+                }
+            };
+            match proxy.type_().await {
+                Ok(DeviceType::Battery) => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    warning!("Failed to get the type of {}: {}", path.as_str(), e);
+                    continue;
+                }
+            }
+
+            match HealthInput::request(&proxy).await {
+                Ok(input) => reports.push(Self::new(input, thresholds)),
+                Err(e) => warning!(
+                    "Failed to get battery health for {}: {}",
+                    proxy.inner().path(),
+                    e
+                ),
+            }
+        }
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(energy_full: f64, capacity: f64, charge_cycles: i32) -> HealthInput {
+        HealthInput {
+            native_path: "BAT0".to_owned(),
+            vendor: "SMP".to_owned(),
+            model: "5B10W13930".to_owned(),
+            serial: "1234".to_owned(),
+            energy_full,
+            energy_full_design: 50.0,
+            capacity,
+            charge_cycles,
+            voltage: 12.0,
+            voltage_min_design: 11.0,
+            voltage_max_design: 13.0,
+        }
+    }
+
+    #[test]
+    fn classes() {
+        let thresholds = HealthThresholds::default();
+        for (energy_full, capacity, cycles, expected) in [
+            (50.0, 100.0, 10, HealthClass::Good),
+            // Newer than new is still just 100%
+            (55.0, 0.0, 10, HealthClass::Good),
+            (40.0, 0.0, 10, HealthClass::Good),
+            (39.5, 0.0, 10, HealthClass::Fair),
+            (30.0, 0.0, 10, HealthClass::Fair),
+            (29.5, 0.0, 10, HealthClass::ReplaceSoon),
+            (10.0, 0.0, -1, HealthClass::ReplaceSoon),
+            // Cycles only matter for otherwise good batteries
+            (50.0, 0.0, 799, HealthClass::Good),
+            (50.0, 0.0, 800, HealthClass::Fair),
+            (20.0, 0.0, 800, HealthClass::ReplaceSoon),
+            // UPower's capacity is used when the energies are unknown
+            (0.0, 90.0, 10, HealthClass::Good),
+            (0.0, 70.0, -1, HealthClass::Fair),
+            (0.0, 50.0, -1, HealthClass::ReplaceSoon),
+            (0.0, 0.0, 10, HealthClass::Unknown),
+        ] {
+            let report = HealthReport::new(input(energy_full, capacity, cycles), &thresholds);
+            assert_eq!(
+                report.class, expected,
+                "{energy_full} Wh, {capacity}%, {cycles} cycles"
+            );
+        }
+    }
+
+    #[test]
+    fn cycles_and_voltage() {
+        let thresholds = HealthThresholds::default();
+        let report = HealthReport::new(input(50.0, 0.0, -1), &thresholds);
+        assert_eq!(report.charge_cycles, None);
+        assert_eq!(report.voltage_in_range, Some(true));
+
+        // A voltage dip on its own doesn't make a battery worse
+        let report = HealthReport::new(
+            HealthInput {
+                voltage: 10.5,
+                ..input(50.0, 0.0, 3)
+            },
+            &thresholds,
+        );
+        assert_eq!(report.charge_cycles, Some(3));
+        assert_eq!(report.voltage_in_range, Some(false));
+        assert_eq!(report.class, HealthClass::Good);
+
+        let report = HealthReport::new(
+            HealthInput {
+                voltage_max_design: 0.0,
+                ..input(50.0, 0.0, 3)
+            },
+            &thresholds,
+        );
+        assert_eq!(report.voltage_in_range, None);
+    }
+
+    #[test]
+    fn json() {
+        let report = HealthReport::new(input(37.5, 0.0, 212), &HealthThresholds::default());
+        assert_eq!(
+            report.to_json().unwrap(),
+            r#"{"native_path":"BAT0","vendor":"SMP","model":"5B10W13930","serial":"1234","state_of_health":75,"capacity":null,"charge_cycles":212,"voltage_in_range":true,"energy_full":37.5,"energy_full_design":50.0,"class":"fair"}"#
+        );
+    }
+}
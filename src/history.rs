@@ -0,0 +1,439 @@
+//! Read the history files upowerd keeps on disk, so battery behaviour can be looked at without the daemon running.
+//!
+//! upowerd saves `history-<kind>-<id>.dat` files, where the id is made from the battery's model, design energy and serial.
+//! Every line is a tab-separated `time  value  state`. UPower's statistics (`GetStatistics`) are computed from the charge history,
+//! so there is nothing separate to read for those.
+//!
+//! When the daemon is running, [`HistoryKind::request`] gets the same data over D-Bus, without needing to read its files.
+use {
+    crate::{
+        logging::*,
+        types::{BatteryState, UPowerName},
+        xmlgen::device::PowerDeviceProxy,
+    },
+    ::core::{fmt, str::FromStr, time::Duration},
+    ::std::{
+        fs, io,
+        path::{Path, PathBuf},
+        time::SystemTime,
+    },
+};
+
+/// Where upowerd keeps its history files
+pub const DEFAULT_HISTORY_DIR: &str = "/var/lib/upower";
+
+/// What a history file records
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    strum_macros::Display,
+    strum_macros::AsRefStr,
+    strum_macros::EnumIter,
+)]
+#[strum(serialize_all = "kebab-case")]
+pub enum HistoryKind {
+    /// The percentage
+    Charge,
+    /// The energy rate, in W
+    Rate,
+    /// The time to full, in seconds
+    TimeFull,
+    /// The time to empty, in seconds
+    TimeEmpty,
+}
+impl HistoryKind {
+    /// Ask upowerd for the last `timespan` of this history, in about `resolution` points, oldest first
+    pub async fn request(
+        self,
+        proxy: &PowerDeviceProxy<'_>,
+        timespan: Duration,
+        resolution: u32,
+    ) -> ::zbus::Result<Vec<HistoryItem>> {
+        let timespan = u32::try_from(timespan.as_secs()).unwrap_or(u32::MAX);
+        let mut items = proxy
+            .get_history(self.as_ref(), timespan, resolution)
+            .await?
+            .into_iter()
+            .map(|(time, value, state)| HistoryItem {
+                time: time as u64,
+                value,
+                state,
+            })
+            .collect::<Vec<_>>();
+
+        // upowerd sends the newest first
+        items.sort_by_key(|i| i.time);
+        Ok(items)
+    }
+}
+
+/// A single point of history
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryItem {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub value: f64,
+    pub state: BatteryState,
+}
+impl HistoryItem {
+    #[inline]
+    pub fn system_time(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(self.time)
+    }
+}
+impl FromStr for HistoryItem {
+    type Err = HistoryItemParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.trim().split('\t').collect::<Vec<_>>();
+        let [time, value, state] = parts[..] else {
+            return Err(HistoryItemParseError::FieldCount(parts.len()));
+        };
+
+        Ok(Self {
+            time: time
+                .parse()
+                .map_err(|_| HistoryItemParseError::InvalidTime(time.to_owned()))?,
+            value: value
+                .parse()
+                .map_err(|_| HistoryItemParseError::InvalidValue(value.to_owned()))?,
+            state: BatteryState::from_upower_name(state)
+                .ok_or_else(|| HistoryItemParseError::InvalidState(state.to_owned()))?,
+        })
+    }
+}
+impl fmt::Display for HistoryItem {
+    /// The same format upowerd writes, without the trailing newline
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{:.3}\t{}",
+            self.time,
+            self.value,
+            self.state.upower_name()
+        )
+    }
+}
+
+/// A history file on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryFile {
+    pub kind: HistoryKind,
+    /// The device id, like `BAT0-50.2-1234`
+    pub id: String,
+    pub path: PathBuf,
+}
+impl HistoryFile {
+    /// Recognize a history file by its name
+    pub fn from_path(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let rest = name.strip_prefix("history-")?.strip_suffix(".dat")?;
+
+        // Longest prefixes first, since `time-full-` would also start with anything shorter
+        let (kind, id) = [
+            (HistoryKind::TimeEmpty, "time-empty-"),
+            (HistoryKind::TimeFull, "time-full-"),
+            (HistoryKind::Charge, "charge-"),
+            (HistoryKind::Rate, "rate-"),
+        ]
+        .into_iter()
+        .find_map(|(kind, prefix)| rest.strip_prefix(prefix).map(|id| (kind, id)))?;
+
+        Some(Self {
+            kind,
+            id: id.to_owned(),
+            path,
+        })
+    }
+
+    /// Find every history file in a directory, sorted by id and then kind
+    pub fn find_all<P: AsRef<Path>>(dir: P) -> Result<Vec<Self>, HistoryError> {
+        let dir = dir.as_ref();
+        let mut files = fs::read_dir(dir)
+            .map_err(|e| HistoryError::Io(dir.to_owned(), e))?
+            .filter_map(|entry| Self::from_path(entry.ok()?.path()))
+            .collect::<Vec<_>>();
+
+        files.sort_by(|a, b| a.id.cmp(&b.id).then((a.kind as u8).cmp(&(b.kind as u8))));
+        Ok(files)
+    }
+
+    /// Find the history files for one device
+    pub fn find_device<P: AsRef<Path>>(dir: P, id: &str) -> Result<Vec<Self>, HistoryError> {
+        let mut files = Self::find_all(dir)?;
+        files.retain(|f| f.id == id);
+        Ok(files)
+    }
+
+    /// Read every item in the file, in the order it was written (oldest first).
+    ///
+    /// Lines that can't be parsed are skipped, like upowerd does when it loads them.
+    pub fn read(&self) -> Result<Vec<HistoryItem>, HistoryError> {
+        let text =
+            fs::read_to_string(&self.path).map_err(|e| HistoryError::Io(self.path.clone(), e))?;
+
+        Ok(text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match line.parse() {
+                Ok(item) => Some(item),
+                Err(e) => {
+                    debug!(
+                        "Skipping invalid history line in {}: {}: {}",
+                        self.path.display(),
+                        e,
+                        line
+                    );
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+/// All of the history for one device
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceHistory {
+    pub id: String,
+    pub charge: Vec<HistoryItem>,
+    pub rate: Vec<HistoryItem>,
+    pub time_full: Vec<HistoryItem>,
+    pub time_empty: Vec<HistoryItem>,
+}
+impl DeviceHistory {
+    /// Read all of the history in a directory, grouped by device
+    pub fn read_all<P: AsRef<Path>>(dir: P) -> Result<Vec<Self>, HistoryError> {
+        let mut devices: Vec<Self> = Vec::new();
+
+        for file in HistoryFile::find_all(dir)? {
+            let items = file.read()?;
+            // `find_all` sorts by id, so the same device is always the last one
+            let device = match devices.last_mut() {
+                Some(d) if d.id == file.id => d,
+                _ => {
+                    devices.push(Self {
+                        id: file.id.clone(),
+                        ..Default::default()
+                    });
+                    devices.last_mut().unwrap()
+                }
+            };
+            *device.get_mut(file.kind) = items;
+        }
+
+        Ok(devices)
+    }
+
+    #[inline]
+    pub fn get(&self, kind: HistoryKind) -> &[HistoryItem] {
+        match kind {
+            HistoryKind::Charge => &self.charge,
+            HistoryKind::Rate => &self.rate,
+            HistoryKind::TimeFull => &self.time_full,
+            HistoryKind::TimeEmpty => &self.time_empty,
+        }
+    }
+
+    #[inline]
+    fn get_mut(&mut self, kind: HistoryKind) -> &mut Vec<HistoryItem> {
+        match kind {
+            HistoryKind::Charge => &mut self.charge,
+            HistoryKind::Rate => &mut self.rate,
+            HistoryKind::TimeFull => &mut self.time_full,
+            HistoryKind::TimeEmpty => &mut self.time_empty,
+        }
+    }
+}
+
+/// Why a line of a history file couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryItemParseError {
+    /// The line has this many tab-separated fields instead of three
+    FieldCount(usize),
+    InvalidTime(String),
+    InvalidValue(String),
+    InvalidState(String),
+}
+impl fmt::Display for HistoryItemParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FieldCount(n) => write!(f, "Expected 3 tab-separated fields, found {n}"),
+            Self::InvalidTime(time) => write!(f, "Invalid time: {time}"),
+            Self::InvalidValue(value) => write!(f, "Invalid value: {value}"),
+            Self::InvalidState(state) => write!(f, "Invalid state: {state}"),
+        }
+    }
+}
+impl std::error::Error for HistoryItemParseError {}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Io(PathBuf, io::Error),
+}
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "Failed to read {}: {}", path.display(), e),
+        }
+    }
+}
+impl std::error::Error for HistoryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(_, e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAPTOP: &str = "5B10W13930-50-1234";
+
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/history")
+    }
+
+    #[test]
+    fn recognizes_file_names() {
+        for (name, expected) in [
+            (
+                "history-charge-BAT0.dat",
+                Some((HistoryKind::Charge, "BAT0")),
+            ),
+            ("history-rate-BAT0.dat", Some((HistoryKind::Rate, "BAT0"))),
+            (
+                "history-time-full-BAT0.dat",
+                Some((HistoryKind::TimeFull, "BAT0")),
+            ),
+            (
+                "history-time-empty-BAT0.dat",
+                Some((HistoryKind::TimeEmpty, "BAT0")),
+            ),
+            // Only the first prefix counts, whatever the id looks like
+            (
+                "history-time-full-charge-BAT0.dat",
+                Some((HistoryKind::TimeFull, "charge-BAT0")),
+            ),
+            (
+                "history-charge-time-empty-BAT0.dat",
+                Some((HistoryKind::Charge, "time-empty-BAT0")),
+            ),
+            ("history-time-BAT0.dat", None),
+            ("history-charge-BAT0.dat.bak", None),
+            ("charge-BAT0.dat", None),
+            ("README", None),
+        ] {
+            let file = HistoryFile::from_path(Path::new("/var/lib/upower").join(name));
+            assert_eq!(
+                file.as_ref().map(|f| (f.kind, f.id.as_str())),
+                expected,
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn items_round_trip() {
+        for line in [
+            "1700000000\t85.000\tdischarging",
+            "1700003840\t100.000\tfully-charged",
+            "1700000240\t35.250\tpending-charge",
+        ] {
+            let item = line.parse::<HistoryItem>().unwrap();
+            assert_eq!(item.to_string(), line);
+        }
+
+        assert_eq!(
+            "1700000000\t85\tdischarging\n".parse(),
+            Ok(HistoryItem {
+                time: 1_700_000_000,
+                value: 85.0,
+                state: BatteryState::Discharging,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_items() {
+        for (line, expected) in [
+            ("", HistoryItemParseError::FieldCount(1)),
+            ("1700000000\t85.000", HistoryItemParseError::FieldCount(2)),
+            (
+                "1700000000\t85.000\tcharging\textra",
+                HistoryItemParseError::FieldCount(4),
+            ),
+            (
+                "-1\t85.000\tcharging",
+                HistoryItemParseError::InvalidTime("-1".to_owned()),
+            ),
+            (
+                "1700000000\tlots\tcharging",
+                HistoryItemParseError::InvalidValue("lots".to_owned()),
+            ),
+            (
+                "1700000000\t85.000\tsideways",
+                HistoryItemParseError::InvalidState("sideways".to_owned()),
+            ),
+        ] {
+            assert_eq!(line.parse::<HistoryItem>(), Err(expected), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn reads_every_device() {
+        let devices = DeviceHistory::read_all(fixtures()).unwrap();
+        assert_eq!(
+            devices.iter().map(|d| d.id.as_str()).collect::<Vec<_>>(),
+            [LAPTOP, "hidpp_battery_0"]
+        );
+
+        let laptop = &devices[0];
+        for (kind, len) in [
+            (HistoryKind::Charge, 4),
+            (HistoryKind::Rate, 3),
+            (HistoryKind::TimeFull, 1),
+            (HistoryKind::TimeEmpty, 2),
+        ] {
+            assert_eq!(laptop.get(kind).len(), len, "{kind}");
+        }
+        assert_eq!(
+            laptop.charge.last().map(|i| (i.value, i.state)),
+            Some((100.0, BatteryState::FullyCharged))
+        );
+        assert_eq!(laptop.time_full[0].value, 3600.0);
+
+        // The blank and invalid lines are skipped
+        let mouse = &devices[1];
+        assert_eq!(
+            mouse.charge.iter().map(|i| i.value).collect::<Vec<_>>(),
+            [40.0, 35.0]
+        );
+        assert!(mouse.rate.is_empty() && mouse.time_full.is_empty());
+    }
+
+    #[test]
+    fn finds_one_device() {
+        let files = HistoryFile::find_device(fixtures(), LAPTOP).unwrap();
+        assert_eq!(
+            files.iter().map(|f| f.kind).collect::<Vec<_>>(),
+            [
+                HistoryKind::Charge,
+                HistoryKind::Rate,
+                HistoryKind::TimeFull,
+                HistoryKind::TimeEmpty
+            ]
+        );
+        assert!(HistoryFile::find_device(fixtures(), "BAT9")
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            HistoryFile::find_all(fixtures().join("missing")),
+            Err(HistoryError::Io(..))
+        ));
+    }
+}
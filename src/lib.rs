@@ -1,5 +1,26 @@
+pub mod aggregate;
+pub mod backlight;
+pub mod bar;
+pub mod broker;
+pub mod cache;
+pub mod charge_limit;
+pub mod chart;
+pub mod config;
+pub mod dashboard;
+pub mod dump;
+pub mod estimator;
+pub mod forecast;
+pub mod health;
+pub mod history;
 mod logging;
+pub mod peripherals;
+pub mod schedule;
+pub mod style;
+pub mod svg;
+pub mod sysfs;
+pub mod threshold;
 pub mod types;
+pub mod warning;
 
 mod xmlgen;
 pub use xmlgen::*;
@@ -41,12 +41,16 @@ mod log {
         };
     }
 
+    #[allow(unused_imports)]
     pub(crate) use debug;
+    #[allow(unused_imports)]
     pub(crate) use info;
+    #[allow(unused_imports)]
     pub(crate) use trace;
     pub(crate) use warning;
 }
 
+#[allow(unused_macros)]
 #[cfg(not(feature = "tracing"))]
 mod log {
     /// A macro for `tracing::debug` that does nothing, because the tracing feature is disabled.
@@ -87,8 +91,11 @@ mod log {
         };
     }
 
+    #[allow(unused_imports)]
     pub(crate) use debug;
+    #[allow(unused_imports)]
     pub(crate) use info;
+    #[allow(unused_imports)]
     pub(crate) use trace;
     pub(crate) use warning;
 }
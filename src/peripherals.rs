@@ -0,0 +1,348 @@
+//! Battery status for peripherals: mice, keyboards, headsets, gamepads, and anything else that isn't powering the system.
+use {
+    crate::{
+        logging::*,
+        types::{
+            BatteryLevel, BatteryState, BuiltinIconSet, DeviceType, IconSet, Percentage,
+            WarningLevel,
+        },
+        xmlgen::{device::PowerDeviceProxy, upower::UPowerProxy},
+    },
+    ::futures_util::{
+        future::ready,
+        stream::{self, BoxStream, SelectAll},
+        StreamExt,
+    },
+    ::serde::{Deserialize, Serialize},
+    ::std::collections::HashSet,
+    ::zbus::zvariant::OwnedObjectPath,
+};
+
+/// A single peripheral and its battery
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Peripheral {
+    pub path: OwnedObjectPath,
+    pub type_: DeviceType,
+    pub vendor: String,
+    pub model: String,
+    pub serial: String,
+    pub percentage: Percentage,
+    /// Devices that only report a coarse level will have something other than `None` here
+    pub battery_level: BatteryLevel,
+    pub state: BatteryState,
+    pub warning_level: WarningLevel,
+    pub is_present: bool,
+    pub icon_name: String,
+}
+impl Peripheral {
+    /// Whether a device with these properties counts as a peripheral
+    #[inline]
+    pub fn is_peripheral(type_: DeviceType, power_supply: bool) -> bool {
+        !power_supply && !matches!(type_, DeviceType::LinePower | DeviceType::Unknown)
+    }
+
+    /// The exact percentage if the device reports one, otherwise an approximation of its coarse level.
+    pub fn approximate_percentage(&self) -> Percentage {
+        self.battery_level
+            .approximate_percentage()
+            .unwrap_or(self.percentage)
+    }
+
+    /// The device's glyph and its battery icon, like `🖱️ 🔋`. Coarse battery levels use their approximate percentage,
+    /// and critical devices get the critical icon.
+    pub fn glyph(&self, set: BuiltinIconSet, icons: &IconSet) -> String {
+        match self.is_present {
+            true => self.type_.glyph_with_battery(
+                set,
+                icons,
+                self.approximate_percentage(),
+                self.state,
+                self.warning_level,
+            ),
+            false => format!("{} {}", self.type_.glyph(set), icons.not_present),
+        }
+    }
+
+    /// A human-readable name, like "Logitech MX Master 3"
+    pub fn name(&self) -> String {
+        match (self.vendor.is_empty(), self.model.is_empty()) {
+            (false, false) => format!("{} {}", self.vendor, self.model),
+            (true, false) => self.model.clone(),
+            (false, true) => self.vendor.clone(),
+            (true, true) => self.type_.to_string(),
+        }
+    }
+
+    /// Request the details of a peripheral.
+    ///
+    /// Returns None if the device is not a peripheral, or it is not one of `kinds` (if it isn't empty).
+    pub async fn request(
+        proxy: &PowerDeviceProxy<'_>,
+        kinds: &[DeviceType],
+    ) -> ::zbus::Result<Option<Self>> {
+        let (type_, power_supply) = ::futures_util::join!(proxy.type_(), proxy.power_supply());
+        let type_ = type_?;
+
+        if !Self::is_peripheral(type_, power_supply?)
+            || !(kinds.is_empty() || kinds.contains(&type_))
+        {
+            return Ok(None);
+        }
+
+        let (
+            vendor,
+            model,
+            serial,
+            percentage,
+            battery_level,
+            state,
+            warning_level,
+            is_present,
+            icon_name,
+        ) = ::futures_util::join!(
+            proxy.vendor(),
+            proxy.model(),
+            proxy.serial(),
+            proxy.percentage(),
+            proxy.battery_level(),
+            proxy.state(),
+            proxy.warning_level(),
+            proxy.is_present(),
+            proxy.icon_name(),
+        );
+
+        Ok(Some(Self {
+            path: OwnedObjectPath::from(proxy.inner().path().to_owned()),
+            type_,
+            vendor: vendor?,
+            model: model?,
+            serial: serial?,
+            percentage: percentage?,
+            battery_level: battery_level?,
+            state: state?,
+            warning_level: warning_level?,
+            is_present: is_present?,
+            icon_name: icon_name?,
+        }))
+    }
+
+    /// Watch this peripheral, yielding its new details every time its battery changes.
+    pub async fn watch(
+        proxy: PowerDeviceProxy<'static>,
+    ) -> impl ::futures_util::Stream<Item = Self> + Send + 'static {
+        let percentage = proxy.receive_percentage_changed().await.map(|_| ());
+        let state = proxy.receive_state_changed().await.map(|_| ());
+        let is_present = proxy.receive_is_present_changed().await.map(|_| ());
+        let battery_level = proxy.receive_battery_level_changed().await.map(|_| ());
+        let warning_level = proxy.receive_warning_level_changed().await.map(|_| ());
+
+        stream::select(
+            stream::select(percentage, battery_level),
+            stream::select(state, stream::select(is_present, warning_level)),
+        )
+        .then(move |_| {
+            let proxy = proxy.clone();
+            async move { Self::request(&proxy, &[]).await }
+        })
+        .filter_map(|result| {
+            ready(match result {
+                Ok(peripheral) => peripheral,
+                Err(e) => {
+                    warning!("Failed to update peripheral: {}", e);
+                    None
+                }
+            })
+        })
+    }
+}
+
+/// List every peripheral UPower knows about. If `kinds` is not empty, only those kinds of devices are returned.
+///
+/// Devices that can't be read are logged and left out.
+pub async fn list(
+    connection: &::zbus::Connection,
+    upower: &UPowerProxy<'_>,
+    kinds: &[DeviceType],
+) -> ::zbus::Result<Vec<Peripheral>> {
+    let mut peripherals = Vec::new();
+
+    for path in upower.enumerate_devices().await? {
+        let proxy = match PowerDeviceProxy::new(connection, path.clone()).await {
+            Ok(p) => p,
+            Err(e) => {
+                warning!("Failed to connect to {}: {}", path.as_str(), e);
+                continue;
+            }
+        };
+        match Peripheral::request(&proxy, kinds).await {
+            Ok(Some(peripheral)) => peripherals.push(peripheral),
+            Ok(None) => {}
+            Err(e) => warning!(
+                "Failed to get peripheral details for {}: {}",
+                path.as_str(),
+                e
+            ),
+        }
+    }
+
+    Ok(peripherals)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeripheralEvent {
+    /// A peripheral was connected, or it was there when watching started
+    Added(Peripheral),
+    /// A peripheral's battery changed
+    Changed(Peripheral),
+    Removed(OwnedObjectPath),
+}
+
+enum Update {
+    Added(OwnedObjectPath),
+    Changed(Peripheral),
+    Removed(OwnedObjectPath),
+}
+
+/// Watch every peripheral, including ones that get connected later.
+///
+/// This yields an [`PeripheralEvent::Added`] event for every peripheral that is already connected first.
+/// A device that shows up both in that list and in a `DeviceAdded` signal is only reported once.
+pub async fn watch_all(
+    connection: ::zbus::Connection,
+    upower: UPowerProxy<'static>,
+    kinds: Vec<DeviceType>,
+) -> ::zbus::Result<impl ::futures_util::Stream<Item = PeripheralEvent> + Send + 'static> {
+    let added = upower.receive_device_added().await?.filter_map(|signal| {
+        ready(
+            signal
+                .args()
+                .ok()
+                .map(|args| Update::Added(args.device.into())),
+        )
+    });
+    let removed = upower.receive_device_removed().await?.filter_map(|signal| {
+        ready(
+            signal
+                .args()
+                .ok()
+                .map(|args| Update::Removed(args.device.into())),
+        )
+    });
+
+    let existing = upower.enumerate_devices().await?;
+
+    let mut updates: SelectAll<BoxStream<'static, Update>> = SelectAll::new();
+    updates.push(added.boxed());
+    updates.push(removed.boxed());
+    updates.push(stream::iter(existing.into_iter().map(Update::Added)).boxed());
+
+    // Property streams don't end when a device is removed, so a device that comes back can reuse its old one.
+    let watched = HashSet::<OwnedObjectPath>::new();
+    // The peripherals that have been added and not removed since
+    let connected = HashSet::<OwnedObjectPath>::new();
+
+    let events = stream::unfold(
+        (connection, kinds, updates, watched, connected),
+        |(connection, kinds, mut updates, mut watched, mut connected)| async move {
+            loop {
+                let event = match updates.next().await? {
+                    Update::Added(path) if connected.contains(&path) => continue,
+                    Update::Added(path) => {
+                        let proxy = match PowerDeviceProxy::new(&connection, path).await {
+                            Ok(p) => p,
+                            Err(e) => {
+                                warning!("Failed to make a proxy for a new device: {}", e);
+                                continue;
+                            }
+                        };
+
+                        match Peripheral::request(&proxy, &kinds).await {
+                            Ok(Some(peripheral)) => {
+                                connected.insert(peripheral.path.clone());
+                                if watched.insert(peripheral.path.clone()) {
+                                    updates.push(
+                                        Peripheral::watch(proxy).await.map(Update::Changed).boxed(),
+                                    );
+                                }
+                                PeripheralEvent::Added(peripheral)
+                            }
+                            Ok(None) => continue,
+                            Err(e) => {
+                                warning!("Failed to get peripheral details: {}", e);
+                                continue;
+                            }
+                        }
+                    }
+                    Update::Changed(peripheral) => PeripheralEvent::Changed(peripheral),
+                    Update::Removed(path) if connected.remove(&path) => {
+                        PeripheralEvent::Removed(path)
+                    }
+                    Update::Removed(_) => continue,
+                };
+
+                return Some((event, (connection, kinds, updates, watched, connected)));
+            }
+        },
+    );
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mouse(warning_level: WarningLevel) -> Peripheral {
+        Peripheral {
+            path: OwnedObjectPath::try_from(
+                "/org/freedesktop/UPower/devices/mouse_hidpp_battery_0",
+            )
+            .unwrap(),
+            type_: DeviceType::Mouse,
+            vendor: "Logitech".to_owned(),
+            model: "MX Master 3".to_owned(),
+            serial: String::new(),
+            percentage: Percentage::new_saturating(5),
+            battery_level: BatteryLevel::None,
+            state: BatteryState::Discharging,
+            warning_level,
+            is_present: true,
+            icon_name: String::new(),
+        }
+    }
+
+    #[test]
+    fn critical_peripherals_get_the_critical_glyph() {
+        let icons = IconSet::ascii();
+        let glyph = |p: &Peripheral| p.glyph(BuiltinIconSet::Ascii, &icons);
+        let mouse_glyph = DeviceType::Mouse.glyph(BuiltinIconSet::Ascii);
+
+        assert_eq!(
+            glyph(&mouse(WarningLevel::Critical)),
+            format!("{mouse_glyph} {}", icons.critical)
+        );
+        assert_ne!(
+            glyph(&mouse(WarningLevel::None)),
+            format!("{mouse_glyph} {}", icons.critical)
+        );
+        assert_eq!(
+            glyph(&Peripheral {
+                is_present: false,
+                ..mouse(WarningLevel::Critical)
+            }),
+            format!("{mouse_glyph} {}", icons.not_present)
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(mouse(WarningLevel::None).name(), "Logitech MX Master 3");
+        let unnamed = Peripheral {
+            vendor: String::new(),
+            model: String::new(),
+            ..mouse(WarningLevel::None)
+        };
+        assert_eq!(unnamed.name(), DeviceType::Mouse.to_string());
+    }
+}
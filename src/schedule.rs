@@ -0,0 +1,282 @@
+//! "Charge to full by" scheduling.
+//!
+//! Keeps the battery held at a charge limit, and lifts the limit just early enough that the battery
+//! reaches 100% by a deadline, based on how fast it has been observed to charge.
+use {
+    crate::{
+        charge_limit::{ChargeLimitError, ChargeLimiter},
+        logging::*,
+        sysfs::{SysfsBattery, SysfsError},
+        types::{BatteryState, Percentage},
+        xmlgen::DisplayDeviceDetails,
+    },
+    ::core::time::Duration,
+    ::std::time::SystemTime,
+};
+
+/// Where the scheduler gets the current time from, so it can be tested without waiting
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+impl<F: Fn() -> SystemTime> Clock for F {
+    #[inline]
+    fn now(&self) -> SystemTime {
+        self()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FullChargeSchedule {
+    /// When the battery should be full
+    pub deadline: SystemTime,
+    /// The end threshold to hold the battery at the rest of the time
+    pub hold_at: Percentage,
+    /// Extra time to start early by, because charging slows down near the top
+    pub margin: Duration,
+    /// How long a full charge is assumed to take before any charging has been observed
+    pub assumed_full_charge: Duration,
+}
+impl FullChargeSchedule {
+    pub fn new(deadline: SystemTime, hold_at: Percentage) -> Self {
+        Self {
+            deadline,
+            hold_at,
+            margin: Duration::from_secs(30 * 60),
+            assumed_full_charge: Duration::from_secs(2 * 60 * 60),
+        }
+    }
+}
+
+/// What the end threshold should be right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleAction {
+    /// Keep the end threshold at this level
+    Hold(Percentage),
+    /// Lift the limit, and charge all the way
+    ChargeFull,
+}
+impl ScheduleAction {
+    /// The end threshold this action wants
+    pub const fn end_threshold(self) -> Percentage {
+        match self {
+            Self::Hold(p) => p,
+            Self::ChargeFull => Percentage::MAX,
+        }
+    }
+
+    /// Apply this action by writing the end threshold to sysfs
+    pub fn apply_sysfs(self, battery: &SysfsBattery) -> Result<(), SysfsError> {
+        battery.set_end(self.end_threshold())
+    }
+
+    /// Apply this action through UPower. UPower can't change the threshold values,
+    /// so this turns the system's configured limit on to hold, and off to charge to full.
+    pub async fn apply_upower(self, limiter: &ChargeLimiter<'_>) -> Result<(), ChargeLimitError> {
+        limiter.set_enabled(matches!(self, Self::Hold(_))).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChargeScheduler<C: Clock = SystemClock> {
+    clock: C,
+    schedule: FullChargeSchedule,
+    /// The smoothed charging rate, in W
+    charge_rate: Option<f64>,
+}
+impl<C: Clock> ChargeScheduler<C> {
+    const RATE_SMOOTHING: f64 = 0.3;
+
+    pub const fn new(clock: C, schedule: FullChargeSchedule) -> Self {
+        Self {
+            clock,
+            schedule,
+            charge_rate: None,
+        }
+    }
+
+    #[inline]
+    pub const fn schedule(&self) -> &FullChargeSchedule {
+        &self.schedule
+    }
+
+    /// Whether the deadline has passed
+    pub fn is_finished(&self) -> bool {
+        self.clock.now() >= self.schedule.deadline
+    }
+
+    /// Record the charging rate, if the battery is charging
+    pub fn observe(&mut self, details: &DisplayDeviceDetails) {
+        if details.state != BatteryState::Charging || details.energy_rate.abs() <= f64::EPSILON {
+            return;
+        }
+
+        let rate = details.energy_rate.abs();
+        self.charge_rate = Some(match self.charge_rate {
+            Some(smoothed) => smoothed + Self::RATE_SMOOTHING * (rate - smoothed),
+            None => rate,
+        });
+    }
+
+    /// How long it will take to charge from the current energy to full.
+    ///
+    /// A rate so small that the time doesn't fit in a [`Duration`] comes out as [`Duration::MAX`].
+    pub fn time_to_full(&self, details: &DisplayDeviceDetails) -> Duration {
+        let missing = (details.energy_full - details.energy).max(0.0);
+
+        let hours = match self.charge_rate {
+            Some(rate) => missing / rate,
+            None if details.energy_full > 0.0 => {
+                self.schedule.assumed_full_charge.as_secs_f64() / 3600.0 * missing
+                    / details.energy_full
+            }
+            // Without any energy info, assume the worst
+            None => self.schedule.assumed_full_charge.as_secs_f64() / 3600.0,
+        };
+
+        Duration::try_from_secs_f64(hours.max(0.0) * 3600.0).unwrap_or(Duration::MAX)
+    }
+
+    /// When full charging has to start to make the deadline
+    pub fn start_time(&self, details: &DisplayDeviceDetails) -> SystemTime {
+        let lead = self
+            .time_to_full(details)
+            .saturating_add(self.schedule.margin);
+        self.schedule
+            .deadline
+            .checked_sub(lead)
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+
+    /// Observe the new details, and decide what the end threshold should be now.
+    pub fn update(&mut self, details: &DisplayDeviceDetails) -> ScheduleAction {
+        self.observe(details);
+
+        let now = self.clock.now();
+        let action = if now >= self.schedule.deadline {
+            ScheduleAction::Hold(self.schedule.hold_at)
+        } else if now >= self.start_time(details) {
+            ScheduleAction::ChargeFull
+        } else {
+            ScheduleAction::Hold(self.schedule.hold_at)
+        };
+
+        trace!("Charge schedule decided {:?}", action);
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        ::std::{cell::Cell, rc::Rc},
+    };
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// A clock that only moves when it's told to
+    fn clock(start: SystemTime) -> (Rc<Cell<SystemTime>>, impl Clock) {
+        let now = Rc::new(Cell::new(start));
+        let handle = now.clone();
+        (now, move || handle.get())
+    }
+
+    fn details(state: BatteryState, energy: f64, energy_rate: f64) -> DisplayDeviceDetails {
+        DisplayDeviceDetails {
+            state,
+            energy,
+            energy_full: 50.0,
+            energy_rate,
+            ..Default::default()
+        }
+    }
+
+    fn scheduler(start: SystemTime) -> (Rc<Cell<SystemTime>>, ChargeScheduler<impl Clock>) {
+        let (now, clock) = clock(start);
+        let schedule = FullChargeSchedule::new(start + 10 * HOUR, Percentage::new_saturating(80));
+        (now, ChargeScheduler::new(clock, schedule))
+    }
+
+    #[test]
+    fn assumes_a_full_charge_time_before_observing_any() {
+        let start = SystemTime::UNIX_EPOCH + 1000 * HOUR;
+        let (now, mut scheduler) = scheduler(start);
+        let idle = details(BatteryState::PendingCharge, 40.0, 0.0);
+
+        // 20% missing of an assumed 2 hours, plus the 30 minute margin
+        assert_eq!(scheduler.time_to_full(&idle), Duration::from_secs(24 * 60));
+        assert_eq!(
+            scheduler.start_time(&idle),
+            start + 10 * HOUR - Duration::from_secs(54 * 60)
+        );
+
+        assert_eq!(
+            scheduler.update(&idle),
+            ScheduleAction::Hold(Percentage::new_saturating(80))
+        );
+        now.set(start + 9 * HOUR + Duration::from_secs(6 * 60));
+        assert_eq!(scheduler.update(&idle), ScheduleAction::ChargeFull);
+    }
+
+    #[test]
+    fn uses_the_observed_rate() {
+        let start = SystemTime::UNIX_EPOCH + 1000 * HOUR;
+        let (now, mut scheduler) = scheduler(start);
+
+        scheduler.update(&details(BatteryState::Charging, 30.0, 10.0));
+        let held = details(BatteryState::PendingCharge, 40.0, 0.0);
+        // 10 Wh at 10 W
+        assert_eq!(scheduler.time_to_full(&held), HOUR);
+
+        now.set(start + 8 * HOUR);
+        assert_eq!(
+            scheduler.update(&held),
+            ScheduleAction::Hold(Percentage::new_saturating(80))
+        );
+        now.set(start + 8 * HOUR + Duration::from_secs(30 * 60));
+        assert_eq!(scheduler.update(&held), ScheduleAction::ChargeFull);
+
+        // Rates are smoothed, not replaced, so this makes it 13 W
+        scheduler.observe(&details(BatteryState::Charging, 40.0, 20.0));
+        assert_eq!(
+            scheduler.time_to_full(&held).as_secs(),
+            (10.0 / 13.0 * 3600.0) as u64
+        );
+    }
+
+    #[test]
+    fn holds_again_after_the_deadline() {
+        let start = SystemTime::UNIX_EPOCH + 1000 * HOUR;
+        let (now, mut scheduler) = scheduler(start);
+        assert!(!scheduler.is_finished());
+
+        now.set(start + 10 * HOUR);
+        assert!(scheduler.is_finished());
+        assert_eq!(
+            scheduler.update(&details(BatteryState::Charging, 45.0, 10.0)),
+            ScheduleAction::Hold(Percentage::new_saturating(80))
+        );
+    }
+
+    #[test]
+    fn tiny_rates_dont_panic() {
+        let start = SystemTime::UNIX_EPOCH + 1000 * HOUR;
+        let (_, mut scheduler) = scheduler(start);
+
+        // Too slow for the time to fit in a Duration
+        let trickle = details(BatteryState::Charging, 10.0, 1e-15);
+        assert_eq!(scheduler.update(&trickle), ScheduleAction::ChargeFull);
+        assert_eq!(scheduler.time_to_full(&trickle), Duration::MAX);
+        assert_eq!(scheduler.start_time(&trickle), SystemTime::UNIX_EPOCH);
+    }
+}
@@ -0,0 +1,290 @@
+//! Color battery text by its state and warning level, as Pango markup, ANSI escapes, or polybar/lemonbar tags.
+use {
+    crate::{
+        types::{BatteryState, IconSet, WarningLevel},
+        xmlgen::DisplayDeviceDetails,
+    },
+    ::core::{fmt, str::FromStr},
+    ::std::borrow::Cow,
+};
+
+/// A 24-bit color, written like `#rrggbb`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+impl FromStr for Rgb {
+    type Err = ColorParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().strip_prefix('#').unwrap_or(s.trim());
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(ColorParseError(s.to_owned()));
+        }
+
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| ColorParseError(s.to_owned()))
+        };
+        Ok(Self(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorParseError(pub String);
+impl fmt::Display for ColorParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid color, expected #rrggbb: {}", self.0)
+    }
+}
+impl std::error::Error for ColorParseError {}
+
+/// Which color to use for what. `None` leaves the text uncolored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// Discharging with no warning, or anything else not covered below
+    pub normal: Option<Rgb>,
+    pub charging: Option<Rgb>,
+    pub full: Option<Rgb>,
+    pub low: Option<Rgb>,
+    pub critical: Option<Rgb>,
+    pub action: Option<Rgb>,
+}
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            normal: None,
+            charging: Some(Rgb(0xa6, 0xe3, 0xa1)),
+            full: Some(Rgb(0x89, 0xb4, 0xfa)),
+            low: Some(Rgb(0xf9, 0xe2, 0xaf)),
+            critical: Some(Rgb(0xf3, 0x8b, 0xa8)),
+            action: Some(Rgb(0xe6, 0x45, 0x53)),
+        }
+    }
+}
+impl Palette {
+    /// The warning level wins over the state, so a battery that is about to run out is always marked.
+    pub fn color(&self, state: BatteryState, warning_level: WarningLevel) -> Option<Rgb> {
+        match (warning_level, state) {
+            (WarningLevel::Action, _) => self.action,
+            (WarningLevel::Critical, _) => self.critical,
+            (WarningLevel::Low, _) => self.low,
+            (_, BatteryState::Charging | BatteryState::PendingCharge) => self.charging,
+            (_, BatteryState::FullyCharged) => self.full,
+            _ => self.normal,
+        }
+    }
+}
+
+/// The output format
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Default,
+    strum_macros::Display,
+    strum_macros::AsRefStr,
+    strum_macros::EnumString,
+    strum_macros::EnumIter,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "kebab-case")]
+pub enum Markup {
+    /// No colors at all
+    #[default]
+    Plain,
+    /// `<span foreground="#rrggbb">`, for Waybar, i3blocks and anything else that uses Pango
+    Pango,
+    /// 24-bit ANSI escape sequences, for terminals
+    Ansi,
+    /// `%{F#rrggbb}` tags, which polybar and lemonbar both understand
+    Polybar,
+}
+impl Markup {
+    /// Make text safe to put in this format.
+    ///
+    /// Anything that comes from the daemon (vendor, model, ...) should go through here, since it could contain markup.
+    /// Control characters are removed for every format, so they can't inject escape sequences or break the line.
+    pub fn escape(self, text: &str) -> Cow<'_, str> {
+        let needs_escape = |c: char| {
+            c.is_control()
+                || match self {
+                    Self::Pango => matches!(c, '&' | '<' | '>' | '\'' | '"'),
+                    Self::Polybar => c == '%',
+                    Self::Plain | Self::Ansi => false,
+                }
+        };
+        if !text.contains(needs_escape) {
+            return Cow::Borrowed(text);
+        }
+
+        let mut escaped = String::with_capacity(text.len() + 8);
+        for c in text.chars() {
+            match (self, c) {
+                (_, c) if c.is_control() => {}
+                (Self::Pango, '&') => escaped.push_str("&amp;"),
+                (Self::Pango, '<') => escaped.push_str("&lt;"),
+                (Self::Pango, '>') => escaped.push_str("&gt;"),
+                (Self::Pango, '\'') => escaped.push_str("&apos;"),
+                (Self::Pango, '"') => escaped.push_str("&quot;"),
+                (Self::Polybar, '%') => escaped.push_str("%%"),
+                (_, c) => escaped.push(c),
+            }
+        }
+        Cow::Owned(escaped)
+    }
+
+    /// Wrap text that is already escaped in a color
+    pub fn colorize(self, escaped: &str, color: Option<Rgb>) -> String {
+        let Some(color) = color else {
+            return escaped.to_owned();
+        };
+        match self {
+            Self::Plain => escaped.to_owned(),
+            Self::Pango => format!(r#"<span foreground="{color}">{escaped}</span>"#),
+            Self::Ansi => format!(
+                "\x1b[38;2;{};{};{}m{escaped}\x1b[39m",
+                color.0, color.1, color.2
+            ),
+            Self::Polybar => format!("%{{F{color}}}{escaped}%{{F-}}"),
+        }
+    }
+}
+
+/// Puts a [`Markup`] and a [`Palette`] together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Styler {
+    pub markup: Markup,
+    pub palette: Palette,
+}
+impl Styler {
+    #[inline]
+    pub const fn new(markup: Markup, palette: Palette) -> Self {
+        Self { markup, palette }
+    }
+
+    /// Escape text, and color it for a state and warning level
+    pub fn style(&self, text: &str, state: BatteryState, warning_level: WarningLevel) -> String {
+        self.markup.colorize(
+            &self.markup.escape(text),
+            self.palette.color(state, warning_level),
+        )
+    }
+
+    /// The usual battery string, like `󰁾 55%`, styled for the device's state
+    pub fn battery(&self, details: &DisplayDeviceDetails, icons: &IconSet) -> String {
+        let text = format!("{} {}%", icons.icon_for(details), details.percentage.get());
+        self.style(&text, details.state, details.warning_level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes() {
+        for (markup, text, expected) in [
+            (Markup::Pango, "Logitech", "Logitech"),
+            (
+                Markup::Pango,
+                r#"<b>"M&M's"</b>"#,
+                "&lt;b&gt;&quot;M&amp;M&apos;s&quot;&lt;/b&gt;",
+            ),
+            (Markup::Polybar, "50% off", "50%% off"),
+            (Markup::Polybar, "%{F#ff0000}", "%%{F#ff0000}"),
+            // Only each format's own special characters are touched
+            (Markup::Polybar, "<b>&</b>", "<b>&</b>"),
+            (Markup::Pango, "100%", "100%"),
+            (Markup::Plain, "<b>100%</b>", "<b>100%</b>"),
+            // Control characters are dropped everywhere
+            (Markup::Ansi, "\x1b[31mred\x1b[0m", "[31mred[0m"),
+            (Markup::Plain, "two\nlines\r\t", "twolines"),
+            (Markup::Pango, "a\x07<", "a&lt;"),
+        ] {
+            assert_eq!(markup.escape(text), expected, "{markup} {text:?}");
+        }
+
+        assert!(matches!(
+            Markup::Pango.escape("nothing to do"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn colorizes() {
+        let red = Some(Rgb(0xff, 0, 0x10));
+        for (markup, expected) in [
+            (Markup::Plain, "text"),
+            (Markup::Pango, r##"<span foreground="#ff0010">text</span>"##),
+            (Markup::Ansi, "\x1b[38;2;255;0;16mtext\x1b[39m"),
+            (Markup::Polybar, "%{F#ff0010}text%{F-}"),
+        ] {
+            assert_eq!(markup.colorize("text", red), expected, "{markup}");
+            assert_eq!(markup.colorize("text", None), "text", "{markup}");
+        }
+    }
+
+    #[test]
+    fn palette_colors() {
+        let palette = Palette {
+            normal: Some(Rgb(0, 0, 0)),
+            ..Palette::default()
+        };
+        for (state, warning_level, expected) in [
+            (
+                BatteryState::Discharging,
+                WarningLevel::None,
+                palette.normal,
+            ),
+            (BatteryState::Unknown, WarningLevel::None, palette.normal),
+            (BatteryState::Charging, WarningLevel::None, palette.charging),
+            (
+                BatteryState::PendingCharge,
+                WarningLevel::None,
+                palette.charging,
+            ),
+            (BatteryState::FullyCharged, WarningLevel::None, palette.full),
+            (BatteryState::Discharging, WarningLevel::Low, palette.low),
+            // The warning level wins over the state
+            (BatteryState::Charging, WarningLevel::Low, palette.low),
+            (
+                BatteryState::Charging,
+                WarningLevel::Critical,
+                palette.critical,
+            ),
+            (
+                BatteryState::FullyCharged,
+                WarningLevel::Action,
+                palette.action,
+            ),
+        ] {
+            assert_eq!(
+                palette.color(state, warning_level),
+                expected,
+                "{state:?} {warning_level:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!("#a6e3a1".parse(), Ok(Rgb(0xa6, 0xe3, 0xa1)));
+        assert_eq!(" A6E3A1 ".parse(), Ok(Rgb(0xa6, 0xe3, 0xa1)));
+        assert_eq!(Rgb(0xa6, 0xe3, 0xa1).to_string(), "#a6e3a1");
+        for invalid in ["", "#fff", "#a6e3a1ff", "#gggggg", "#a6e3é"] {
+            assert!(invalid.parse::<Rgb>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn styles_daemon_text() {
+        let styler = Styler::new(Markup::Pango, Palette::default());
+        assert_eq!(
+            styler.style("<3", BatteryState::Charging, WarningLevel::None),
+            r##"<span foreground="#a6e3a1">&lt;3</span>"##
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Plot charge and rate history as an SVG image, for attaching to bug reports.
+//!
+//! The SVG is written out as text, so this works anywhere, including on headless machines.
+use {
+    crate::{
+        chart::short_duration,
+        config::UPowerConfig,
+        history::HistoryItem,
+        style::Markup,
+        types::{BatteryState, Percentage},
+    },
+    ::core::fmt::{self, Write},
+};
+
+/// A horizontal line across the charge axis
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    pub label: String,
+    pub percentage: Percentage,
+}
+impl Threshold {
+    /// The low, critical and action thresholds from upowerd's config
+    pub fn from_config(config: &UPowerConfig) -> Vec<Self> {
+        let config = config.effective();
+        [
+            ("low", config.percentage_low),
+            ("critical", config.percentage_critical),
+            ("action", config.percentage_action),
+        ]
+        .into_iter()
+        .map(|(label, percentage)| Self {
+            label: label.to_owned(),
+            percentage,
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvgChart<'a> {
+    /// Percentage history, drawn against the left axis. The state regions and plug events come from here.
+    pub charge: &'a [HistoryItem],
+    /// Energy rate history in W, drawn against the right axis
+    pub rate: &'a [HistoryItem],
+    /// In px
+    pub width: u32,
+    /// In px
+    pub height: u32,
+    pub title: String,
+    pub thresholds: Vec<Threshold>,
+}
+impl<'a> SvgChart<'a> {
+    const MARGIN_LEFT: f64 = 50.0;
+    const MARGIN_RIGHT: f64 = 60.0;
+    const MARGIN_TOP: f64 = 40.0;
+    const MARGIN_BOTTOM: f64 = 40.0;
+
+    const CHARGE_COLOR: &'static str = "#1565c0";
+    const RATE_COLOR: &'static str = "#6a1b9a";
+    const THRESHOLD_COLOR: &'static str = "#c62828";
+
+    pub fn new(charge: &'a [HistoryItem], rate: &'a [HistoryItem]) -> Self {
+        Self {
+            charge,
+            rate,
+            width: 800,
+            height: 400,
+            title: "Battery history".to_owned(),
+            thresholds: Vec::new(),
+        }
+    }
+
+    /// The background color of a state's region, or None to leave it blank
+    pub const fn state_color(state: BatteryState) -> Option<&'static str> {
+        match state {
+            BatteryState::Charging => Some("#a5d6a7"),
+            BatteryState::Discharging => Some("#ffcc80"),
+            BatteryState::FullyCharged => Some("#90caf9"),
+            BatteryState::Empty => Some("#ef9a9a"),
+            BatteryState::PendingCharge | BatteryState::PendingDischarge => Some("#e0e0e0"),
+            BatteryState::Unknown => None,
+        }
+    }
+
+    /// Whether a state means the charger is plugged in. Some states don't say either way.
+    const fn on_external_power(state: BatteryState) -> Option<bool> {
+        match state {
+            BatteryState::Charging | BatteryState::FullyCharged | BatteryState::PendingCharge => {
+                Some(true)
+            }
+            BatteryState::Discharging | BatteryState::Empty => Some(false),
+            BatteryState::PendingDischarge | BatteryState::Unknown => None,
+        }
+    }
+
+    /// The times where the charger was plugged in (true) or unplugged (false)
+    pub fn plug_events(&self) -> Vec<(u64, bool)> {
+        let mut events = Vec::new();
+        let mut plugged = None;
+
+        for item in self.charge {
+            let Some(now) = Self::on_external_power(item.state) else {
+                continue;
+            };
+            if plugged.is_some_and(|p| p != now) {
+                events.push((item.time, now));
+            }
+            plugged = Some(now);
+        }
+
+        events
+    }
+
+    pub fn render(&self) -> String {
+        self.to_string()
+    }
+}
+impl fmt::Display for SvgChart<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (width, height) = (self.width as f64, self.height as f64);
+        let (left, top) = (Self::MARGIN_LEFT, Self::MARGIN_TOP);
+        let right = (width - Self::MARGIN_RIGHT).max(left + 1.0);
+        let bottom = (height - Self::MARGIN_BOTTOM).max(top + 1.0);
+
+        let times = self.charge.iter().chain(self.rate).map(|i| i.time);
+        let start = times.clone().min().unwrap_or_default();
+        let end = times.max().unwrap_or_default().max(start + 1);
+
+        // Round the top of the rate axis up to something even
+        let max_rate = self.rate.iter().map(|i| i.value.abs()).fold(0.0, f64::max);
+        let rate_top = match max_rate > 0.0 {
+            true => (max_rate / 5.0).ceil() * 5.0,
+            false => 5.0,
+        };
+
+        let x = |time: u64| left + (time - start) as f64 / (end - start) as f64 * (right - left);
+        let y_charge = |value: f64| bottom - value.clamp(0.0, 100.0) / 100.0 * (bottom - top);
+        let y_rate = |value: f64| bottom - (value.abs() / rate_top).min(1.0) * (bottom - top);
+
+        writeln!(
+            f,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" font-family="sans-serif" font-size="11">"#,
+            self.width, self.height, self.width, self.height
+        )?;
+        writeln!(f, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+        writeln!(
+            f,
+            r#"<text x="{:.1}" y="20" font-size="14" text-anchor="middle">{}</text>"#,
+            width / 2.0,
+            // Pango markup is XML, so its escaping works for SVG too
+            Markup::Pango.escape(&self.title)
+        )?;
+
+        // State regions, each run of the same state reaching until the next state starts
+        let mut run_end = 0;
+        for run in self.charge.chunk_by(|a, b| a.state == b.state) {
+            run_end += run.len();
+            let Some(color) = Self::state_color(run[0].state) else {
+                continue;
+            };
+            let from = run[0].time;
+            let to = self.charge.get(run_end).map_or(end, |n| n.time);
+            writeln!(
+                f,
+                r#"<rect x="{:.1}" y="{top:.1}" width="{:.1}" height="{:.1}" fill="{color}" fill-opacity="0.4"/>"#,
+                x(from),
+                x(to) - x(from),
+                bottom - top,
+            )?;
+        }
+
+        // Axes
+        writeln!(
+            f,
+            r#"<path d="M{left:.1} {top:.1}V{bottom:.1}H{right:.1}V{top:.1}" fill="none" stroke="black"/>"#
+        )?;
+        for percentage in [0.0, 25.0, 50.0, 75.0, 100.0] {
+            writeln!(
+                f,
+                r#"<text x="{:.1}" y="{:.1}" text-anchor="end" fill="{}">{percentage}%</text>"#,
+                left - 4.0,
+                y_charge(percentage) + 4.0,
+                Self::CHARGE_COLOR,
+            )?;
+        }
+        for rate in [0.0, rate_top / 2.0, rate_top] {
+            writeln!(
+                f,
+                r#"<text x="{:.1}" y="{:.1}" fill="{}">{rate} W</text>"#,
+                right + 4.0,
+                y_rate(rate) + 4.0,
+                Self::RATE_COLOR,
+            )?;
+        }
+        writeln!(
+            f,
+            r#"<text x="{left:.1}" y="{:.1}">-{}</text>"#,
+            bottom + 16.0,
+            short_duration(end - start)
+        )?;
+        writeln!(
+            f,
+            r#"<text x="{right:.1}" y="{:.1}" text-anchor="end">0</text>"#,
+            bottom + 16.0
+        )?;
+
+        for threshold in self.thresholds.iter() {
+            let y = y_charge(threshold.percentage.get() as f64);
+            writeln!(
+                f,
+                r#"<line x1="{left:.1}" y1="{y:.1}" x2="{right:.1}" y2="{y:.1}" stroke="{}" stroke-dasharray="6 3"/>"#,
+                Self::THRESHOLD_COLOR,
+            )?;
+            writeln!(
+                f,
+                r#"<text x="{:.1}" y="{:.1}" fill="{}">{} ({}%)</text>"#,
+                left + 4.0,
+                y - 3.0,
+                Self::THRESHOLD_COLOR,
+                Markup::Pango.escape(&threshold.label),
+                threshold.percentage.get(),
+            )?;
+        }
+
+        for (time, plugged) in self.plug_events() {
+            let x = x(time);
+            writeln!(
+                f,
+                r#"<line x1="{x:.1}" y1="{top:.1}" x2="{x:.1}" y2="{bottom:.1}" stroke="black" stroke-dasharray="2 2"/>"#
+            )?;
+            writeln!(
+                f,
+                r#"<text x="{x:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+                top - 4.0,
+                match plugged {
+                    true => "plugged in",
+                    false => "unplugged",
+                }
+            )?;
+        }
+
+        let polyline = |f: &mut fmt::Formatter<'_>,
+                        items: &[HistoryItem],
+                        y: &dyn Fn(f64) -> f64,
+                        color: &str|
+         -> fmt::Result {
+            if items.is_empty() {
+                return Ok(());
+            }
+            let mut points = String::new();
+            for item in items {
+                write!(points, "{:.1},{:.1} ", x(item.time), y(item.value))?;
+            }
+            writeln!(
+                f,
+                r#"<polyline points="{}" fill="none" stroke="{color}" stroke-width="2"/>"#,
+                points.trim_end()
+            )
+        };
+        polyline(f, self.rate, &y_rate, Self::RATE_COLOR)?;
+        polyline(f, self.charge, &y_charge, Self::CHARGE_COLOR)?;
+
+        writeln!(f, "</svg>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(points: &[(u64, BatteryState)]) -> Vec<HistoryItem> {
+        points
+            .iter()
+            .map(|&(time, state)| HistoryItem {
+                time,
+                value: 50.0,
+                state,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plug_events() {
+        use BatteryState::*;
+
+        for (states, expected) in [
+            (vec![], vec![]),
+            (vec![Discharging, Discharging], vec![]),
+            (
+                vec![Discharging, Charging, FullyCharged, Discharging],
+                vec![(1, true), (3, false)],
+            ),
+            // States that don't say whether the charger is in are skipped, and don't reset anything
+            (
+                vec![Discharging, Unknown, Discharging, PendingDischarge],
+                vec![],
+            ),
+            (
+                vec![Charging, Unknown, PendingDischarge, Empty],
+                vec![(3, false)],
+            ),
+            (vec![Unknown, PendingCharge, Discharging], vec![(2, false)]),
+        ] {
+            let points = history(
+                &states
+                    .iter()
+                    .enumerate()
+                    .map(|(i, state)| (i as u64, *state))
+                    .collect::<Vec<_>>(),
+            );
+            assert_eq!(
+                SvgChart::new(&points, &[]).plug_events(),
+                expected,
+                "{states:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn renders() {
+        let charge = history(&[
+            (0, BatteryState::Discharging),
+            (3600, BatteryState::Charging),
+        ]);
+        let rate = [HistoryItem {
+            time: 0,
+            value: 12.5,
+            state: BatteryState::Discharging,
+        }];
+        let chart = SvgChart {
+            title: "BAT0 <SMP & \"co\">".to_owned(),
+            thresholds: vec![Threshold {
+                label: "low".to_owned(),
+                percentage: Percentage::new_saturating(20),
+            }],
+            ..SvgChart::new(&charge, &rate)
+        };
+        let svg = chart.render();
+
+        assert!(svg.starts_with("<svg "), "{svg}");
+        assert!(svg.ends_with("</svg>\n"), "{svg}");
+        assert!(
+            svg.contains(">BAT0 &lt;SMP &amp; &quot;co&quot;&gt;</text>"),
+            "{svg}"
+        );
+        // 20% of the way up from the bottom at 360px to the top at 40px
+        assert!(
+            svg.contains(r##"<line x1="50.0" y1="296.0" x2="740.0" y2="296.0" stroke="#c62828""##),
+            "{svg}"
+        );
+        assert!(svg.contains(">low (20%)</text>"), "{svg}");
+        assert!(svg.contains(">plugged in</text>"), "{svg}");
+        assert!(svg.contains(">-1h 0m</text>"), "{svg}");
+        assert_eq!(svg.matches("<polyline ").count(), 2, "{svg}");
+    }
+}
@@ -0,0 +1,413 @@
+//! Charge thresholds straight from sysfs, for systems whose UPower is too old to do it for us.
+//!
+//! Vendor drivers don't agree on the attribute names, so this looks for either
+//! `charge_control_start_threshold`/`charge_control_end_threshold` or `charge_start_threshold`/`charge_stop_threshold`.
+//! Some drivers only have an end threshold.
+//!
+//! Writing needs root, or a udev rule that makes the attributes writable.
+use {
+    crate::{logging::*, types::Percentage},
+    ::core::fmt,
+    ::std::{
+        fs, io,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Where the kernel puts power supplies
+pub const DEFAULT_POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply";
+
+/// The attribute names a driver uses for its thresholds
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ThresholdAttributes {
+    /// Not every driver has a start threshold
+    pub start: Option<&'static str>,
+    pub end: &'static str,
+}
+impl ThresholdAttributes {
+    /// The standard names, used by most drivers in recent kernels
+    pub const CHARGE_CONTROL: Self = Self {
+        start: Some("charge_control_start_threshold"),
+        end: "charge_control_end_threshold",
+    };
+    /// The older names, still used by some vendor drivers
+    pub const LEGACY: Self = Self {
+        start: Some("charge_start_threshold"),
+        end: "charge_stop_threshold",
+    };
+
+    /// Figure out which names the driver for this power supply uses
+    pub fn detect(battery_dir: &Path) -> Option<Self> {
+        [Self::CHARGE_CONTROL, Self::LEGACY]
+            .into_iter()
+            .find(|attrs| battery_dir.join(attrs.end).is_file())
+            .map(|attrs| Self {
+                start: attrs.start.filter(|s| battery_dir.join(s).is_file()),
+                end: attrs.end,
+            })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SysfsThresholds {
+    pub start: Option<Percentage>,
+    pub end: Percentage,
+}
+impl SysfsThresholds {
+    /// Make sure the start threshold is below the end threshold
+    pub fn validate(&self) -> Result<(), SysfsError> {
+        match self.start {
+            Some(start) if start >= self.end => Err(SysfsError::InvalidOrder {
+                start,
+                end: self.end,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the end threshold has to be written before the start threshold when going from `current` to these,
+    /// so the pair stays valid in between
+    fn end_first(&self, current: &Self) -> bool {
+        self.start.is_some_and(|start| start >= current.end)
+    }
+}
+
+#[derive(Debug)]
+pub enum SysfsError {
+    Io(io::Error),
+    /// The driver for this battery has no charge threshold attributes
+    Unsupported,
+    /// The driver has no start threshold, but one was given
+    NoStartThreshold,
+    InvalidOrder {
+        start: Percentage,
+        end: Percentage,
+    },
+    /// An attribute held something other than a percentage
+    InvalidValue(String),
+}
+impl From<io::Error> for SysfsError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl fmt::Display for SysfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "sysfs I/O error: {e}"),
+            Self::Unsupported => f.write_str("This battery does not support charge thresholds"),
+            Self::NoStartThreshold => f.write_str("This battery only supports an end threshold"),
+            Self::InvalidOrder { start, end } => write!(
+                f,
+                "The start threshold ({start}) must be below the end threshold ({end})"
+            ),
+            Self::InvalidValue(v) => write!(f, "Invalid threshold value: {v}"),
+        }
+    }
+}
+impl std::error::Error for SysfsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A battery in sysfs that supports charge thresholds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysfsBattery {
+    path: PathBuf,
+    attributes: ThresholdAttributes,
+}
+impl SysfsBattery {
+    /// Open the battery with this name (like `BAT0`) under `root`. Use [`DEFAULT_POWER_SUPPLY_ROOT`] for the real sysfs.
+    pub fn open(root: impl AsRef<Path>, name: &str) -> Result<Self, SysfsError> {
+        let path = root.as_ref().join(name);
+        if !path.is_dir() {
+            return Err(SysfsError::Io(io::Error::from(io::ErrorKind::NotFound)));
+        }
+
+        let attributes = ThresholdAttributes::detect(&path).ok_or(SysfsError::Unsupported)?;
+        Ok(Self { path, attributes })
+    }
+
+    /// Find all the batteries (`BAT*`) under `root` that support charge thresholds
+    pub fn find_all(root: impl AsRef<Path>) -> io::Result<Vec<Self>> {
+        let mut batteries = Vec::new();
+
+        for entry in fs::read_dir(root.as_ref())? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str().filter(|n| n.starts_with("BAT")) else {
+                continue;
+            };
+
+            match Self::open(root.as_ref(), name) {
+                Ok(battery) => batteries.push(battery),
+                Err(e) => debug!("Skipping battery {}: {}", name, e),
+            }
+        }
+
+        batteries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(batteries)
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The name of the battery, like `BAT0`
+    pub fn name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+    }
+
+    #[inline]
+    pub const fn attributes(&self) -> ThresholdAttributes {
+        self.attributes
+    }
+
+    fn read_attribute(&self, attribute: &str) -> Result<Percentage, SysfsError> {
+        let contents = fs::read_to_string(self.path.join(attribute))?;
+        let value = contents.trim();
+
+        value
+            .parse::<u8>()
+            .ok()
+            .and_then(Percentage::new)
+            .ok_or_else(|| SysfsError::InvalidValue(value.to_owned()))
+    }
+
+    fn write_attribute(&self, attribute: &str, value: Percentage) -> Result<(), SysfsError> {
+        debug!("Writing {} to {}/{}", value, self.path.display(), attribute);
+        fs::write(self.path.join(attribute), value.get().to_string())?;
+        Ok(())
+    }
+
+    pub fn thresholds(&self) -> Result<SysfsThresholds, SysfsError> {
+        let start = match self.attributes.start {
+            Some(attribute) => Some(self.read_attribute(attribute)?),
+            None => None,
+        };
+
+        Ok(SysfsThresholds {
+            start,
+            end: self.read_attribute(self.attributes.end)?,
+        })
+    }
+
+    /// Set both thresholds at once. Without a start threshold, this is the same as [`Self::set_end`].
+    ///
+    /// Some drivers refuse a start threshold above the current end threshold (and vice versa),
+    /// so the values are written in whichever order keeps the pair valid the whole time.
+    pub fn set_thresholds(&self, thresholds: SysfsThresholds) -> Result<(), SysfsError> {
+        let Some(start) = thresholds.start else {
+            return self.set_end(thresholds.end);
+        };
+        thresholds.validate()?;
+        let start_attribute = self.attributes.start.ok_or(SysfsError::NoStartThreshold)?;
+
+        let current = self.thresholds()?;
+        if thresholds.end_first(&current) {
+            self.write_attribute(self.attributes.end, thresholds.end)?;
+            self.write_attribute(start_attribute, start)
+        } else {
+            self.write_attribute(start_attribute, start)?;
+            self.write_attribute(self.attributes.end, thresholds.end)
+        }
+    }
+
+    /// Set only the end threshold, keeping the start threshold as it is
+    pub fn set_end(&self, end: Percentage) -> Result<(), SysfsError> {
+        let current = self.thresholds()?;
+        SysfsThresholds { end, ..current }.validate()?;
+        self.write_attribute(self.attributes.end, end)
+    }
+
+    /// Set only the start threshold, keeping the end threshold as it is
+    pub fn set_start(&self, start: Percentage) -> Result<(), SysfsError> {
+        let attribute = self.attributes.start.ok_or(SysfsError::NoStartThreshold)?;
+        let current = self.thresholds()?;
+        SysfsThresholds {
+            start: Some(start),
+            ..current
+        }
+        .validate()?;
+        self.write_attribute(attribute, start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fake power supply root, with one directory per battery and its attributes
+    struct FakeSysfs(PathBuf);
+    impl FakeSysfs {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir()
+                .join(format!("upowerz-test-{}-sysfs-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            Self(root)
+        }
+
+        fn battery(&self, name: &str, attributes: &[(&str, &str)]) -> &Self {
+            let dir = self.0.join(name);
+            fs::create_dir_all(&dir).unwrap();
+            for (attribute, value) in attributes {
+                fs::write(dir.join(attribute), format!("{value}\n")).unwrap();
+            }
+            self
+        }
+
+        fn read(&self, battery: &str, attribute: &str) -> String {
+            fs::read_to_string(self.0.join(battery).join(attribute)).unwrap()
+        }
+    }
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn percent(p: u8) -> Percentage {
+        Percentage::new_saturating(p)
+    }
+
+    #[test]
+    fn detects_attribute_names() {
+        let sysfs = FakeSysfs::new("detect");
+        sysfs
+            .battery(
+                "BAT0",
+                &[
+                    ("charge_control_start_threshold", "40"),
+                    ("charge_control_end_threshold", "80"),
+                ],
+            )
+            .battery(
+                "BAT1",
+                &[
+                    ("charge_start_threshold", "40"),
+                    ("charge_stop_threshold", "80"),
+                ],
+            )
+            .battery("BAT2", &[("charge_control_end_threshold", "80")])
+            .battery("BAT3", &[("capacity", "50")]);
+
+        let attributes = |name| SysfsBattery::open(&sysfs.0, name).map(|b| b.attributes());
+        assert_eq!(
+            attributes("BAT0").unwrap(),
+            ThresholdAttributes::CHARGE_CONTROL
+        );
+        assert_eq!(attributes("BAT1").unwrap(), ThresholdAttributes::LEGACY);
+        assert_eq!(
+            attributes("BAT2").unwrap(),
+            ThresholdAttributes {
+                start: None,
+                end: "charge_control_end_threshold",
+            }
+        );
+        assert!(matches!(attributes("BAT3"), Err(SysfsError::Unsupported)));
+        assert!(matches!(attributes("BAT4"), Err(SysfsError::Io(_))));
+    }
+
+    #[test]
+    fn finds_only_supported_batteries() {
+        let sysfs = FakeSysfs::new("find");
+        sysfs
+            .battery("BAT1", &[("charge_stop_threshold", "80")])
+            .battery("BAT0", &[("charge_control_end_threshold", "80")])
+            .battery("BAT2", &[("capacity", "50")])
+            .battery("AC", &[("charge_control_end_threshold", "80")]);
+
+        let batteries = SysfsBattery::find_all(&sysfs.0).unwrap();
+        let names = batteries.iter().map(SysfsBattery::name).collect::<Vec<_>>();
+        assert_eq!(names, ["BAT0", "BAT1"]);
+    }
+
+    #[test]
+    fn write_order_keeps_the_pair_valid() {
+        let current = SysfsThresholds {
+            start: Some(percent(40)),
+            end: percent(50),
+        };
+        let raise = SysfsThresholds {
+            start: Some(percent(70)),
+            end: percent(80),
+        };
+        let lower = SysfsThresholds {
+            start: Some(percent(20)),
+            end: percent(30),
+        };
+        assert!(raise.end_first(&current));
+        assert!(!lower.end_first(&current));
+
+        let sysfs = FakeSysfs::new("order");
+        sysfs.battery(
+            "BAT0",
+            &[
+                ("charge_control_start_threshold", "40"),
+                ("charge_control_end_threshold", "50"),
+            ],
+        );
+        let battery = SysfsBattery::open(&sysfs.0, "BAT0").unwrap();
+        for thresholds in [raise, lower] {
+            battery.set_thresholds(thresholds).unwrap();
+            assert_eq!(battery.thresholds().unwrap(), thresholds);
+        }
+        assert_eq!(sysfs.read("BAT0", "charge_control_end_threshold"), "30");
+    }
+
+    #[test]
+    fn rejects_invalid_thresholds() {
+        let sysfs = FakeSysfs::new("invalid");
+        sysfs
+            .battery(
+                "BAT0",
+                &[
+                    ("charge_control_start_threshold", "60"),
+                    ("charge_control_end_threshold", "80"),
+                ],
+            )
+            .battery("BAT1", &[("charge_control_end_threshold", "80")]);
+        let battery = SysfsBattery::open(&sysfs.0, "BAT0").unwrap();
+
+        for (start, end) in [(Some(80), 80), (Some(90), 80), (None, 60), (None, 50)] {
+            let result = battery.set_thresholds(SysfsThresholds {
+                start: start.map(percent),
+                end: percent(end),
+            });
+            assert!(
+                matches!(result, Err(SysfsError::InvalidOrder { .. })),
+                "{start:?} {end}"
+            );
+        }
+        // Nothing was written
+        assert_eq!(
+            battery.thresholds().unwrap(),
+            SysfsThresholds {
+                start: Some(percent(60)),
+                end: percent(80),
+            }
+        );
+
+        let end_only = SysfsBattery::open(&sysfs.0, "BAT1").unwrap();
+        assert!(matches!(
+            end_only.set_start(percent(40)),
+            Err(SysfsError::NoStartThreshold)
+        ));
+        end_only
+            .set_thresholds(SysfsThresholds {
+                start: None,
+                end: percent(60),
+            })
+            .unwrap();
+        assert_eq!(sysfs.read("BAT1", "charge_control_end_threshold"), "60");
+    }
+}
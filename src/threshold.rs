@@ -0,0 +1,413 @@
+//! User-defined battery thresholds, independent of the system-wide ones in `/etc/UPower/UPower.conf`.
+//!
+//! Conditions are written in a small text format, like `percentage < 25 while discharging`,
+//! `time_to_empty < 10min` or `percentage >= 80 while charging`. The unit can also be separated by a space, like `10 min`.
+use {
+    crate::{
+        logging::*,
+        types::BatteryState,
+        xmlgen::{display_device::DeviceProxy, DisplayDeviceDetails},
+    },
+    ::core::{fmt, str::FromStr},
+    ::futures_util::{stream, Stream, StreamExt},
+};
+
+/// The property of the device that a [`Condition`] looks at
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    strum_macros::Display,
+    strum_macros::AsRefStr,
+    strum_macros::EnumString,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "snake_case")]
+pub enum Metric {
+    /// In percent
+    Percentage,
+    /// In seconds
+    TimeToEmpty,
+    /// In seconds
+    TimeToFull,
+    /// In Wh
+    Energy,
+    /// In W
+    EnergyRate,
+}
+impl Metric {
+    /// Get the value of this metric from the device, in the units listed on the variant.
+    ///
+    /// UPower reports the time estimates as 0 when it doesn't know them, like while the battery is charging
+    /// for `time_to_empty`, so those come back as None.
+    pub fn value_of(self, details: &DisplayDeviceDetails) -> Option<f64> {
+        match self {
+            Self::Percentage => Some(details.percentage.get() as f64),
+            Self::TimeToEmpty => Self::known_time(details.time_to_empty.as_signed_secs()),
+            Self::TimeToFull => Self::known_time(details.time_to_full.as_signed_secs()),
+            Self::Energy => Some(details.energy),
+            Self::EnergyRate => Some(details.energy_rate),
+        }
+    }
+
+    #[inline]
+    fn known_time(seconds: i64) -> Option<f64> {
+        match seconds {
+            0 => None,
+            s => Some(s as f64),
+        }
+    }
+
+    /// Get the multiplier to convert a value with the given unit suffix into this metric's units.
+    fn unit_multiplier(self, unit: &str) -> Option<f64> {
+        match (self, unit) {
+            (_, "") => Some(1.0),
+            (Self::Percentage, "%") => Some(1.0),
+            (Self::TimeToEmpty | Self::TimeToFull, "s" | "sec") => Some(1.0),
+            (Self::TimeToEmpty | Self::TimeToFull, "m" | "min") => Some(60.0),
+            (Self::TimeToEmpty | Self::TimeToFull, "h" | "hr") => Some(3600.0),
+            (Self::Energy, "wh" | "Wh") => Some(1.0),
+            (Self::EnergyRate, "w" | "W") => Some(1.0),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    strum_macros::Display,
+    strum_macros::AsRefStr,
+    strum_macros::EnumString,
+)]
+pub enum Comparison {
+    #[strum(serialize = "<")]
+    Less,
+    #[strum(serialize = "<=")]
+    LessOrEqual,
+    #[strum(serialize = ">")]
+    Greater,
+    #[strum(serialize = ">=")]
+    GreaterOrEqual,
+}
+impl Comparison {
+    #[inline]
+    pub fn compare(self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Self::Less => lhs < rhs,
+            Self::LessOrEqual => lhs <= rhs,
+            Self::Greater => lhs > rhs,
+            Self::GreaterOrEqual => lhs >= rhs,
+        }
+    }
+}
+
+/// A single condition, like `percentage < 25 while discharging`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub metric: Metric,
+    pub comparison: Comparison,
+    /// The value to compare against, in the units of the [`Metric`]
+    pub value: f64,
+    /// If set, the condition only holds while the battery is in this state
+    pub state: Option<BatteryState>,
+}
+impl Condition {
+    /// Whether the condition holds. It never does while the metric is unknown.
+    pub fn matches(&self, details: &DisplayDeviceDetails) -> bool {
+        if let Some(state) = self.state {
+            if details.state != state {
+                return false;
+            }
+        }
+
+        self.metric
+            .value_of(details)
+            .is_some_and(|value| self.comparison.compare(value, self.value))
+    }
+}
+impl FromStr for Condition {
+    type Err = ConditionParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (expression, state) = match s.split_once(" while ") {
+            Some((expression, state)) => {
+                let state = BatteryState::from_str(state.trim())
+                    .map_err(|_| ConditionParseError::InvalidState(state.trim().to_owned()))?;
+                (expression, Some(state))
+            }
+            None => (s, None),
+        };
+
+        let mut words = expression.split_whitespace();
+
+        let metric = words.next().ok_or(ConditionParseError::Empty)?;
+        let metric = Metric::from_str(metric)
+            .map_err(|_| ConditionParseError::InvalidMetric(metric.to_owned()))?;
+
+        let comparison = words.next().ok_or(ConditionParseError::MissingComparison)?;
+        let comparison = Comparison::from_str(comparison)
+            .map_err(|_| ConditionParseError::InvalidComparison(comparison.to_owned()))?;
+
+        let value = words.next().ok_or(ConditionParseError::MissingValue)?;
+
+        let unit_start = value
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+            .unwrap_or(value.len());
+        let (number, mut unit) = value.split_at(unit_start);
+        // The unit can also be a separate word, like `10 min`
+        if unit.is_empty() {
+            unit = words.next().unwrap_or_default();
+        }
+
+        if let Some(extra) = words.next() {
+            return Err(ConditionParseError::Unexpected(extra.to_owned()));
+        }
+
+        let multiplier = metric
+            .unit_multiplier(unit)
+            .ok_or_else(|| ConditionParseError::InvalidUnit(unit.to_owned()))?;
+        let number = number
+            .parse::<f64>()
+            .map_err(|_| ConditionParseError::InvalidValue(value.to_owned()))?;
+
+        Ok(Self {
+            metric,
+            comparison,
+            value: number * multiplier,
+            state,
+        })
+    }
+}
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.metric, self.comparison, self.value)?;
+        if let Some(state) = self.state {
+            write!(f, " while {state}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionParseError {
+    Empty,
+    InvalidMetric(String),
+    MissingComparison,
+    InvalidComparison(String),
+    MissingValue,
+    InvalidValue(String),
+    InvalidUnit(String),
+    InvalidState(String),
+    Unexpected(String),
+}
+impl fmt::Display for ConditionParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Empty condition"),
+            Self::InvalidMetric(m) => write!(f, "Unknown metric: {m}"),
+            Self::MissingComparison => f.write_str("Missing comparison operator"),
+            Self::InvalidComparison(c) => write!(f, "Invalid comparison operator: {c}"),
+            Self::MissingValue => f.write_str("Missing value to compare against"),
+            Self::InvalidValue(v) => write!(f, "Invalid value: {v}"),
+            Self::InvalidUnit(u) => write!(f, "Invalid unit for this metric: {u}"),
+            Self::InvalidState(s) => write!(f, "Unknown battery state: {s}"),
+            Self::Unexpected(s) => write!(f, "Unexpected trailing input: {s}"),
+        }
+    }
+}
+impl std::error::Error for ConditionParseError {}
+
+/// A named [`Condition`]. The name is passed along in every [`ThresholdEvent`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub condition: Condition,
+}
+impl Rule {
+    pub fn new(name: impl Into<String>, condition: Condition) -> Self {
+        Self {
+            name: name.into(),
+            condition,
+        }
+    }
+
+    /// Make a rule from the text format described in [the module docs](self)
+    pub fn parse(name: impl Into<String>, condition: &str) -> Result<Self, ConditionParseError> {
+        Ok(Self::new(name, condition.parse()?))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, strum_macros::Display, strum_macros::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ThresholdEventKind {
+    /// The condition started holding
+    Triggered,
+    /// The condition stopped holding
+    Cleared,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThresholdEvent {
+    /// The name of the [`Rule`] that changed
+    pub name: String,
+    pub kind: ThresholdEventKind,
+    pub details: DisplayDeviceDetails,
+}
+
+/// Evaluates a set of [`Rule`]s, only reporting when one of them changes.
+#[derive(Debug, Clone, Default)]
+pub struct ThresholdEngine {
+    rules: Vec<(Rule, bool)>,
+}
+impl ThresholdEngine {
+    pub fn new(rules: impl IntoIterator<Item = Rule>) -> Self {
+        Self {
+            rules: rules.into_iter().map(|r| (r, false)).collect(),
+        }
+    }
+
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.rules.push((rule, false));
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter().map(|(r, _)| r)
+    }
+
+    /// Get the names of all the rules that currently hold
+    pub fn active(&self) -> impl Iterator<Item = &str> {
+        self.rules
+            .iter()
+            .filter(|(_, active)| *active)
+            .map(|(r, _)| r.name.as_str())
+    }
+
+    /// Check every rule against the new details, returning an event for each one that changed.
+    ///
+    /// Rules that already hold the first time this is called are reported as triggered.
+    pub fn evaluate(&mut self, details: &DisplayDeviceDetails) -> Vec<ThresholdEvent> {
+        let mut events = Vec::new();
+
+        for (rule, active) in self.rules.iter_mut() {
+            let matches = rule.condition.matches(details);
+            if matches == *active {
+                continue;
+            }
+            *active = matches;
+
+            let kind = match matches {
+                true => ThresholdEventKind::Triggered,
+                false => ThresholdEventKind::Cleared,
+            };
+            debug!("Threshold rule {} {}", rule.name, kind);
+
+            events.push(ThresholdEvent {
+                name: rule.name.clone(),
+                kind,
+                details: details.clone(),
+            });
+        }
+
+        events
+    }
+}
+
+/// Watch a device, evaluating the rules every time it changes.
+///
+/// This works for the display device as well as any specific device you have a proxy for.
+pub async fn watch<'a>(
+    proxy: &'a DeviceProxy<'a>,
+    mut engine: ThresholdEngine,
+) -> impl Stream<Item = ThresholdEvent> + 'a {
+    DisplayDeviceDetails::watch(proxy)
+        .await
+        .flat_map(move |details| stream::iter(engine.evaluate(&details)))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::types::IntSeconds};
+
+    fn details(state: BatteryState, time_to_empty: i64) -> DisplayDeviceDetails {
+        DisplayDeviceDetails {
+            state,
+            time_to_empty: IntSeconds::new_from_signed(time_to_empty),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_units() {
+        for text in [
+            "time_to_empty < 10min",
+            "time_to_empty < 10 min",
+            "time_to_empty < 600",
+            "time_to_empty < 600s",
+        ] {
+            let condition = text.parse::<Condition>().unwrap();
+            assert_eq!(condition.metric, Metric::TimeToEmpty, "{text}");
+            assert_eq!(condition.value, 600.0, "{text}");
+        }
+
+        let condition = "percentage >= 80 % while charging"
+            .parse::<Condition>()
+            .unwrap();
+        assert_eq!(condition.value, 80.0);
+        assert_eq!(condition.state, Some(BatteryState::Charging));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(
+            "time_to_empty < 10 min later".parse::<Condition>(),
+            Err(ConditionParseError::Unexpected("later".to_owned()))
+        );
+        assert_eq!(
+            "percentage < 10 min".parse::<Condition>(),
+            Err(ConditionParseError::InvalidUnit("min".to_owned()))
+        );
+        assert_eq!(
+            "voltage < 10".parse::<Condition>(),
+            Err(ConditionParseError::InvalidMetric("voltage".to_owned()))
+        );
+        assert_eq!("".parse::<Condition>(), Err(ConditionParseError::Empty));
+    }
+
+    #[test]
+    fn unknown_times_never_match() {
+        let condition = "time_to_empty < 10min".parse::<Condition>().unwrap();
+        assert!(!condition.matches(&details(BatteryState::Charging, 0)));
+        assert!(!condition.matches(&details(BatteryState::FullyCharged, 0)));
+        assert!(condition.matches(&details(BatteryState::Discharging, 300)));
+        assert!(!condition.matches(&details(BatteryState::Discharging, 900)));
+    }
+
+    #[test]
+    fn reports_only_changes() {
+        let mut engine =
+            ThresholdEngine::new([Rule::parse("low", "time_to_empty < 10 min").unwrap()]);
+
+        let kinds =
+            |events: Vec<ThresholdEvent>| events.into_iter().map(|e| e.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds(engine.evaluate(&details(BatteryState::Charging, 0))),
+            []
+        );
+        assert_eq!(
+            kinds(engine.evaluate(&details(BatteryState::Discharging, 300))),
+            [ThresholdEventKind::Triggered]
+        );
+        assert_eq!(
+            kinds(engine.evaluate(&details(BatteryState::Discharging, 200))),
+            []
+        );
+        assert_eq!(
+            kinds(engine.evaluate(&details(BatteryState::Charging, 0))),
+            [ThresholdEventKind::Cleared]
+        );
+    }
+}
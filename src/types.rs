@@ -1,5 +1,5 @@
 use {
-    crate::logging::*,
+    crate::{logging::*, xmlgen::DisplayDeviceDetails},
     ::core::{ops::Deref, str::FromStr, time::Duration},
     ::serde::{Deserialize, Serialize},
     ::serde_repr::{Deserialize_repr, Serialize_repr},
@@ -14,8 +14,9 @@ macro_rules! zvariant {
                     Ok(v) => Self::from_repr(v).unwrap_or_default(),
                     Err(e) => {
                         warning!(
-                            "Failed to convert zvariant value into {}: {e}",
+                            "Failed to convert zvariant value into {}: {}",
                             stringify!($enum),
+                            e,
                         );
                         Self::default()
                     }
@@ -25,6 +26,66 @@ macro_rules! zvariant {
     };
 }
 
+/// Conversions to and from the strings upowerd itself uses, like `line-power`, `fully-charged` or `HybridSleep`.
+///
+/// These match what `upower -i` prints and what shows up in upowerd's logs
+/// (`up_device_kind_to_string`, `up_device_state_to_string` and friends).
+/// They are separate from `Display`/`FromStr`, which keep their own spellings.
+pub trait UPowerName: Sized + strum::IntoEnumIterator + AsRef<str> {
+    /// The name upowerd uses for this value. Defaults to the `AsRef<str>` spelling.
+    #[inline]
+    fn upower_name(&self) -> &str {
+        self.as_ref()
+    }
+
+    /// Parse a name that upowerd uses. Case is ignored, but nothing else is.
+    fn from_upower_name(name: &str) -> Option<Self> {
+        Self::iter().find(|v| v.upower_name().eq_ignore_ascii_case(name))
+    }
+}
+impl UPowerName for BatteryState {}
+impl UPowerName for WarningLevel {}
+impl UPowerName for BatteryLevel {}
+impl UPowerName for Technology {}
+impl UPowerName for CapacityLevel {}
+impl UPowerName for DeviceType {
+    /// The kebab-case names from `up_device_kind_to_string`
+    fn upower_name(&self) -> &str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::LinePower => "line-power",
+            Self::Battery => "battery",
+            Self::Ups => "ups",
+            Self::Monitor => "monitor",
+            Self::Mouse => "mouse",
+            Self::Keyboard => "keyboard",
+            Self::Pda => "pda",
+            Self::Phone => "phone",
+            Self::MediaPlayer => "media-player",
+            Self::Tablet => "tablet",
+            Self::Computer => "computer",
+            Self::GamingInput => "gaming-input",
+            Self::Pen => "pen",
+            Self::Touchpad => "touchpad",
+            Self::Modem => "modem",
+            Self::Network => "network",
+            Self::Headset => "headset",
+            Self::Speakers => "speakers",
+            Self::Headphones => "headphones",
+            Self::Video => "video",
+            Self::OtherAudio => "other-audio",
+            Self::RemoteControl => "remote-control",
+            Self::Printer => "printer",
+            Self::Scanner => "scanner",
+            Self::Camera => "camera",
+            Self::Wearable => "wearable",
+            Self::Toy => "toy",
+            Self::BluetoothGeneric => "bluetooth-generic",
+        }
+    }
+}
+impl UPowerName for CriticalAction {}
+
 /// The current state of the battery, an enum based on its representation in upower
 ///
 /// For upower, this is well-defined. For sysfs, check out `/usr/lib/modules/<kernel>/build/include/linux/power_supply.h`
@@ -39,6 +100,7 @@ macro_rules! zvariant {
     strum_macros::FromRepr,
     strum_macros::AsRefStr,
     strum_macros::EnumString,
+    strum_macros::EnumIter,
     Type,
     Deserialize_repr,
     Serialize_repr,
@@ -68,6 +130,7 @@ zvariant!(u32 => BatteryState);
     strum_macros::FromRepr,
     strum_macros::AsRefStr,
     strum_macros::EnumString,
+    strum_macros::EnumIter,
     Type,
     Deserialize_repr,
     Serialize_repr,
@@ -86,7 +149,24 @@ pub enum WarningLevel {
     Action = 5,
 }
 zvariant!(u32 => WarningLevel);
+impl WarningLevel {
+    /// How severe this warning level is, from 0 (`None`) up to 4 (`Action`).
+    ///
+    /// Returns None for `Unknown`, because it can't be ordered against the others.
+    pub const fn severity(self) -> Option<u8> {
+        match self {
+            Self::Unknown => None,
+            Self::None => Some(0),
+            Self::Discharging => Some(1),
+            Self::Low => Some(2),
+            Self::Critical => Some(3),
+            Self::Action => Some(4),
+        }
+    }
+}
 
+/// The coarse battery level, for devices that can't report an exact percentage.
+///
 /// Source: https://upower.freedesktop.org/docs/Device.html
 #[derive(
     Debug,
@@ -99,6 +179,124 @@ zvariant!(u32 => WarningLevel);
     strum_macros::FromRepr,
     strum_macros::AsRefStr,
     strum_macros::EnumString,
+    strum_macros::EnumIter,
+    Type,
+    Deserialize_repr,
+    Serialize_repr,
+)]
+#[repr(u32)]
+#[strum(ascii_case_insensitive, serialize_all = "kebab-case")]
+pub enum BatteryLevel {
+    #[default]
+    Unknown = 0,
+    /// The battery does not use a coarse level of battery reporting
+    None = 1,
+    Low = 3,
+    Critical = 4,
+    Normal = 6,
+    High = 7,
+    Full = 8,
+}
+zvariant!(u32 => BatteryLevel);
+impl BatteryLevel {
+    /// Get a rough percentage for this level, for picking an icon.
+    ///
+    /// Returns None if the device doesn't use coarse levels, or the level is unknown.
+    pub const fn approximate_percentage(self) -> Option<Percentage> {
+        match self {
+            Self::Unknown | Self::None => None,
+            Self::Critical => Some(Percentage(5)),
+            Self::Low => Some(Percentage(10)),
+            Self::Normal => Some(Percentage(55)),
+            Self::High => Some(Percentage(80)),
+            Self::Full => Some(Percentage(100)),
+        }
+    }
+}
+
+/// The battery chemistry
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Default,
+    strum_macros::Display,
+    strum_macros::FromRepr,
+    strum_macros::AsRefStr,
+    strum_macros::EnumString,
+    strum_macros::EnumIter,
+    Type,
+    Deserialize_repr,
+    Serialize_repr,
+)]
+#[repr(u32)]
+#[strum(ascii_case_insensitive, serialize_all = "kebab-case")]
+pub enum Technology {
+    #[default]
+    Unknown = 0,
+    LithiumIon = 1,
+    LithiumPolymer = 2,
+    LithiumIronPhosphate = 3,
+    LeadAcid = 4,
+    NickelCadmium = 5,
+    NickelMetalHydride = 6,
+}
+zvariant!(u32 => Technology);
+
+/// The coarse capacity level, straight from the kernel's `capacity_level` attribute.
+///
+/// Like [`CriticalAction`], UPower sends this as a String. It is empty for devices that don't report it.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Default,
+    strum_macros::Display,
+    strum_macros::AsRefStr,
+    strum_macros::EnumString,
+    strum_macros::EnumIter,
+    Type,
+    Deserialize,
+    Serialize,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum CapacityLevel {
+    #[default]
+    Unknown,
+    Critical,
+    Low,
+    Normal,
+    High,
+    Full,
+}
+impl TryFrom<OwnedValue> for CapacityLevel {
+    type Error = ::zbus::zvariant::Error;
+    fn try_from(value: OwnedValue) -> Result<Self, Self::Error> {
+        let value_string: String = value.try_into()?;
+
+        let me = Self::from_str(&value_string).unwrap_or_default();
+        Ok(me)
+    }
+}
+
+/// Source: https://upower.freedesktop.org/docs/Device.html
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Default,
+    strum_macros::Display,
+    strum_macros::FromRepr,
+    strum_macros::AsRefStr,
+    strum_macros::EnumString,
+    strum_macros::EnumIter,
+    strum_macros::IntoStaticStr,
     Type,
     Deserialize_repr,
     Serialize_repr,
@@ -154,6 +352,7 @@ zvariant!(u32 => DeviceType);
     strum_macros::FromRepr,
     strum_macros::AsRefStr,
     strum_macros::EnumString,
+    strum_macros::EnumIter,
     Type,
     Deserialize,
     Serialize,
@@ -164,6 +363,10 @@ pub enum CriticalAction {
     HybridSleep,
     Hibernate,
     PowerOff,
+    /// Only allowed when `AllowRiskyCriticalPowerAction` is set in the UPower config
+    Suspend,
+    /// Only allowed when `AllowRiskyCriticalPowerAction` is set in the UPower config
+    Ignore,
 }
 impl TryFrom<OwnedValue> for CriticalAction {
     type Error = ::zbus::zvariant::Error;
@@ -196,7 +399,25 @@ impl Percentage {
     /// Tries to make a new percentage. Returns None if the integer was invalid.
     pub const fn new(input: u8) -> Option<Self> {
         match input > Self::MAX.0 {
-            true => Some(Self(input)),
+            true => None,
+            false => Some(Self(input)),
+        }
+    }
+
+    /// Make a new percentage, clamping anything above 100 down to 100.
+    pub const fn new_saturating(input: u8) -> Self {
+        match Self::new(input) {
+            Some(v) => v,
+            None => Self::MAX,
+        }
+    }
+
+    /// Make a new percentage from a float, rounding it to the nearest integer.
+    /// Returns None if the float was NaN or out of range.
+    pub fn from_f64(input: f64) -> Option<Self> {
+        let rounded = input.round();
+        match (Self::MIN.0 as f64..=Self::MAX.0 as f64).contains(&rounded) {
+            true => Some(Self(rounded as u8)),
             false => None,
         }
     }
@@ -232,10 +453,10 @@ impl AsRef<u8> for Percentage {
         &self.0
     }
 }
-impl Into<u8> for Percentage {
+impl From<Percentage> for u8 {
     #[inline]
-    fn into(self) -> u8 {
-        self.get()
+    fn from(value: Percentage) -> Self {
+        value.get()
     }
 }
 impl ::std::fmt::Display for Percentage {
@@ -269,6 +490,21 @@ impl IntSeconds {
             is_negative: false,
         }
     }
+
+    #[inline]
+    pub const fn is_negative(&self) -> bool {
+        self.is_negative
+    }
+
+    /// Get the number of seconds, with the sign applied
+    #[inline]
+    pub const fn as_signed_secs(&self) -> i64 {
+        let secs = self.duration.as_secs() as i64;
+        match self.is_negative {
+            true => -secs,
+            false => secs,
+        }
+    }
 }
 impl TryFrom<::zbus::zvariant::OwnedValue> for IntSeconds {
     type Error = ::zbus::zvariant::Error;
@@ -276,11 +512,11 @@ impl TryFrom<::zbus::zvariant::OwnedValue> for IntSeconds {
         match value.deref() {
             Value::I32(i) => Ok(Self::new_from_signed(*i as i64)),
             Value::I16(i) => Ok(Self::new_from_signed(*i as i64)),
-            Value::I64(i) => Ok(Self::new_from_signed(*i as i64)),
+            Value::I64(i) => Ok(Self::new_from_signed(*i)),
             Value::U8(i) => Ok(Self::new_from_unsigned(*i as u64)),
             Value::U16(i) => Ok(Self::new_from_unsigned(*i as u64)),
             Value::U32(i) => Ok(Self::new_from_unsigned(*i as u64)),
-            Value::U64(i) => Ok(Self::new_from_unsigned(*i as u64)),
+            Value::U64(i) => Ok(Self::new_from_unsigned(*i)),
 
             Value::F64(f) => Ok(Self::new_from_signed(f.round() as i64)),
             _ => {
@@ -295,3 +531,740 @@ pub const BATTERY_ICONS_CHARGING: [char; 10] = ['󰢟', '󰢜', '󰂆', '󰂇',
 
 pub const BATTERY_ICONS_DISCHARGING: [char; 10] =
     ['󰂎', '󰁺', '󰁻', '󰁼', '󰁽', '󰁾', '󰁿', '󰂀', '󰂁', '󰂂'];
+
+/// The built-in icon sets
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Default,
+    strum_macros::Display,
+    strum_macros::AsRefStr,
+    strum_macros::EnumString,
+    strum_macros::EnumIter,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "kebab-case")]
+pub enum BuiltinIconSet {
+    /// The same glyphs as [`BATTERY_ICONS_CHARGING`] and [`BATTERY_ICONS_DISCHARGING`]
+    #[default]
+    NerdFont,
+    FontAwesome,
+    Emoji,
+    /// Plain text bars, like `[|||  ]`
+    Ascii,
+}
+
+/// A set of battery icons.
+///
+/// The charging and discharging lists go from empty to full, and can be any length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IconSet {
+    pub charging: Vec<String>,
+    pub discharging: Vec<String>,
+    /// For [`BatteryState::FullyCharged`]
+    pub full: String,
+    /// For [`BatteryState::Empty`]
+    pub empty: String,
+    /// For [`BatteryState::Unknown`], and when a level list is empty
+    pub unknown: String,
+    /// For batteries that aren't plugged in
+    pub not_present: String,
+    /// For a critical or action warning level, when the battery isn't charging
+    pub critical: String,
+}
+impl IconSet {
+    pub fn nerd_font() -> Self {
+        Self {
+            charging: BATTERY_ICONS_CHARGING.map(String::from).to_vec(),
+            discharging: BATTERY_ICONS_DISCHARGING.map(String::from).to_vec(),
+            full: "󰁹".to_owned(),
+            empty: "󰂎".to_owned(),
+            unknown: "󰂑".to_owned(),
+            not_present: "󱉝".to_owned(),
+            critical: "󰂃".to_owned(),
+        }
+    }
+
+    pub fn font_awesome() -> Self {
+        let levels = ["", "", "", "", ""];
+        Self {
+            // Font Awesome doesn't have charging batteries, so these get a bolt in front
+            charging: levels.map(|l| format!("{l}")).to_vec(),
+            discharging: levels.map(String::from).to_vec(),
+            full: "".to_owned(),
+            empty: "".to_owned(),
+            unknown: "".to_owned(),
+            not_present: "".to_owned(),
+            critical: "".to_owned(),
+        }
+    }
+
+    pub fn emoji() -> Self {
+        Self {
+            charging: vec!["🔌".to_owned()],
+            discharging: vec![
+                "🪫".to_owned(),
+                "🔋".to_owned(),
+                "🔋".to_owned(),
+                "🔋".to_owned(),
+                "🔋".to_owned(),
+            ],
+            full: "🔋".to_owned(),
+            empty: "🪫".to_owned(),
+            unknown: "❓".to_owned(),
+            not_present: "🚫".to_owned(),
+            critical: "🪫".to_owned(),
+        }
+    }
+
+    pub fn ascii() -> Self {
+        // 0 to 5 bars
+        let bars = (0..=5)
+            .map(|n| format!("[{:<5}]", "|".repeat(n)))
+            .collect::<Vec<_>>();
+        Self {
+            charging: bars.iter().map(|b| format!("{b}+")).collect(),
+            discharging: bars,
+            full: "[|||||]".to_owned(),
+            empty: "[     ]".to_owned(),
+            unknown: "[  ?  ]".to_owned(),
+            not_present: "[  x  ]".to_owned(),
+            critical: "[!    ]".to_owned(),
+        }
+    }
+
+    /// Which of `count` levels a percentage falls in.
+    ///
+    /// The range is split into equal buckets, rounding down, and 100% goes into the top one.
+    /// For 10 levels that makes 0-9% level 0, 10-19% level 1, and 90-100% level 9.
+    pub fn level_index(percentage: Percentage, count: usize) -> Option<usize> {
+        match count {
+            0 => None,
+            _ => Some((percentage.get() as usize * count / 100).min(count - 1)),
+        }
+    }
+
+    /// Pick an icon. In order:
+    ///
+    /// - [`BatteryState::Unknown`] gets `unknown`
+    /// - [`BatteryState::FullyCharged`] gets `full`, and [`BatteryState::Empty`] gets `empty`
+    /// - A critical or action warning level gets `critical`, unless the battery is charging
+    /// - Charging (or about to) picks from `charging`, anything else from `discharging`, by [`Self::level_index`]
+    pub fn icon(
+        &self,
+        percentage: Percentage,
+        state: BatteryState,
+        warning_level: WarningLevel,
+    ) -> &str {
+        let levels = match state {
+            BatteryState::Unknown => return &self.unknown,
+            BatteryState::FullyCharged => return &self.full,
+            BatteryState::Empty => return &self.empty,
+            BatteryState::Charging | BatteryState::PendingCharge => &self.charging,
+            BatteryState::Discharging | BatteryState::PendingDischarge => {
+                if matches!(warning_level, WarningLevel::Critical | WarningLevel::Action) {
+                    return &self.critical;
+                }
+                &self.discharging
+            }
+        };
+
+        match Self::level_index(percentage, levels.len()) {
+            Some(i) => &levels[i],
+            None => &self.unknown,
+        }
+    }
+
+    /// Pick an icon for a device, using `not_present` if it isn't there.
+    pub fn icon_for(&self, details: &DisplayDeviceDetails) -> &str {
+        match details.is_present {
+            true => self.icon(details.percentage, details.state, details.warning_level),
+            false => &self.not_present,
+        }
+    }
+}
+impl Default for IconSet {
+    #[inline]
+    fn default() -> Self {
+        Self::nerd_font()
+    }
+}
+impl From<BuiltinIconSet> for IconSet {
+    fn from(set: BuiltinIconSet) -> Self {
+        match set {
+            BuiltinIconSet::NerdFont => Self::nerd_font(),
+            BuiltinIconSet::FontAwesome => Self::font_awesome(),
+            BuiltinIconSet::Emoji => Self::emoji(),
+            BuiltinIconSet::Ascii => Self::ascii(),
+        }
+    }
+}
+
+impl DeviceType {
+    /// The freedesktop icon name for this kind of device, like `input-mouse`. Add `-symbolic` for the symbolic version.
+    pub const fn icon_name(self) -> &'static str {
+        match self {
+            Self::Unknown => "battery",
+            Self::LinePower => "ac-adapter",
+            Self::Battery => "battery",
+            Self::Ups => "uninterruptible-power-supply",
+            Self::Monitor => "video-display",
+            Self::Mouse => "input-mouse",
+            Self::Keyboard => "input-keyboard",
+            Self::Pda => "pda",
+            Self::Phone => "phone",
+            Self::MediaPlayer => "multimedia-player",
+            Self::Tablet => "input-tablet",
+            Self::Computer => "computer",
+            Self::GamingInput => "input-gaming",
+            Self::Pen => "input-tablet",
+            Self::Touchpad => "input-touchpad",
+            Self::Modem => "modem",
+            Self::Network => "network-wireless",
+            Self::Headset => "audio-headset",
+            Self::Speakers => "audio-speakers",
+            Self::Headphones => "audio-headphones",
+            Self::Video => "camera-video",
+            Self::OtherAudio => "audio-card",
+            Self::RemoteControl => "input-remote",
+            Self::Printer => "printer",
+            Self::Scanner => "scanner",
+            Self::Camera => "camera-photo",
+            Self::Wearable => "watch",
+            Self::Toy => "applications-games",
+            Self::BluetoothGeneric => "bluetooth",
+        }
+    }
+
+    /// A Nerd Font glyph for this kind of device
+    pub const fn nerd_font_glyph(self) -> &'static str {
+        match self {
+            Self::Unknown => "󰂑",
+            Self::LinePower => "󰚥",
+            Self::Battery => "󰁹",
+            Self::Ups => "󰄌",
+            Self::Monitor => "󰍹",
+            Self::Mouse => "󰍽",
+            Self::Keyboard => "󰌌",
+            Self::Pda => "󰄜",
+            Self::Phone => "󰄜",
+            Self::MediaPlayer => "󰝚",
+            Self::Tablet => "󰓶",
+            Self::Computer => "󰌢",
+            Self::GamingInput => "󰊗",
+            Self::Pen => "󰏪",
+            Self::Touchpad => "󰝁",
+            Self::Modem => "󰑩",
+            Self::Network => "󰖩",
+            Self::Headset => "󰋎",
+            Self::Speakers => "󰓃",
+            Self::Headphones => "󰋋",
+            Self::Video => "󰕧",
+            Self::OtherAudio => "󰕾",
+            Self::RemoteControl => "󰑔",
+            Self::Printer => "󰐪",
+            Self::Scanner => "󰚫",
+            Self::Camera => "󰄀",
+            Self::Wearable => "󰓢",
+            Self::Toy => "󱊈",
+            Self::BluetoothGeneric => "󰂯",
+        }
+    }
+
+    /// A Font Awesome glyph for this kind of device
+    pub const fn font_awesome_glyph(self) -> &'static str {
+        match self {
+            Self::Unknown => "",
+            Self::LinePower => "",
+            Self::Battery => "",
+            Self::Ups => "",
+            Self::Monitor => "",
+            Self::Mouse => "",
+            Self::Keyboard => "",
+            Self::Pda => "",
+            Self::Phone => "",
+            Self::MediaPlayer => "",
+            Self::Tablet => "",
+            Self::Computer => "",
+            Self::GamingInput => "",
+            Self::Pen => "",
+            Self::Touchpad => "",
+            Self::Modem => "",
+            Self::Network => "",
+            Self::Headset => "",
+            Self::Speakers => "",
+            Self::Headphones => "",
+            Self::Video => "",
+            Self::OtherAudio => "",
+            Self::RemoteControl => "",
+            Self::Printer => "",
+            Self::Scanner => "",
+            Self::Camera => "",
+            Self::Wearable => "",
+            Self::Toy => "",
+            Self::BluetoothGeneric => "",
+        }
+    }
+
+    /// An emoji for this kind of device
+    pub const fn emoji(self) -> &'static str {
+        match self {
+            Self::Unknown => "❓",
+            Self::LinePower => "🔌",
+            Self::Battery => "🔋",
+            Self::Ups => "⚡",
+            Self::Monitor => "🖥️",
+            Self::Mouse => "🖱️",
+            Self::Keyboard => "⌨️",
+            Self::Pda => "📟",
+            Self::Phone => "📱",
+            Self::MediaPlayer => "🎵",
+            Self::Tablet => "📱",
+            Self::Computer => "💻",
+            Self::GamingInput => "🎮",
+            Self::Pen => "🖊️",
+            Self::Touchpad => "👆",
+            Self::Modem => "📡",
+            Self::Network => "📶",
+            Self::Headset => "🎧",
+            Self::Speakers => "🔊",
+            Self::Headphones => "🎧",
+            Self::Video => "📹",
+            Self::OtherAudio => "🎵",
+            Self::RemoteControl => "📺",
+            Self::Printer => "🖨️",
+            Self::Scanner => "🖨️",
+            Self::Camera => "📷",
+            Self::Wearable => "⌚",
+            Self::Toy => "🧸",
+            Self::BluetoothGeneric => "🔵",
+        }
+    }
+
+    /// The glyph that fits in with an icon set. The ASCII set just gets the name.
+    pub fn glyph(self, set: BuiltinIconSet) -> &'static str {
+        match set {
+            BuiltinIconSet::NerdFont => self.nerd_font_glyph(),
+            BuiltinIconSet::FontAwesome => self.font_awesome_glyph(),
+            BuiltinIconSet::Emoji => self.emoji(),
+            BuiltinIconSet::Ascii => self.into(),
+        }
+    }
+
+    /// This device's glyph followed by its battery icon, like `🖱️ 🔋`
+    pub fn glyph_with_battery(
+        self,
+        set: BuiltinIconSet,
+        icons: &IconSet,
+        percentage: Percentage,
+        state: BatteryState,
+        warning_level: WarningLevel,
+    ) -> String {
+        format!(
+            "{} {}",
+            self.glyph(set),
+            icons.icon(percentage, state, warning_level)
+        )
+    }
+}
+
+/// What an icon name shows
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IconKind {
+    Battery,
+    AcAdapter,
+    /// Anything else, as it was written (without `-symbolic`)
+    Other(String),
+}
+
+/// How full the battery in an icon name is
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum IconLevel {
+    /// `battery-level-N`, in steps of 10
+    Level(u8),
+    Empty,
+    Caution,
+    Low,
+    Good,
+    Full,
+    Missing,
+    /// The icon doesn't show a level
+    None,
+}
+impl IconLevel {
+    /// The named levels, as they appear in icon names
+    const NAMES: [(&'static str, Self); 6] = [
+        ("empty", Self::Empty),
+        ("caution", Self::Caution),
+        ("low", Self::Low),
+        ("good", Self::Good),
+        ("full", Self::Full),
+        ("missing", Self::Missing),
+    ];
+}
+
+/// A parsed `IconName`, like `battery-level-50-charging-symbolic`.
+///
+/// Change any of the parts and format it again to get the matching icon from a different theme or state.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IconName {
+    pub kind: IconKind,
+    pub level: IconLevel,
+    /// `-charging`
+    pub charging: bool,
+    /// `-charged`, which UPower uses for a full battery on external power
+    pub charged: bool,
+    pub symbolic: bool,
+}
+impl FromStr for IconName {
+    type Err = ::core::convert::Infallible;
+    /// Names that aren't battery or AC adapter icons end up as [`IconKind::Other`], so this never fails
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, symbolic) = match s.strip_suffix("-symbolic") {
+            Some(name) => (name, true),
+            None => (s, false),
+        };
+        let other = || Self {
+            kind: IconKind::Other(name.to_owned()),
+            level: IconLevel::None,
+            charging: false,
+            charged: false,
+            symbolic,
+        };
+
+        if name == "ac-adapter" {
+            return Ok(Self {
+                kind: IconKind::AcAdapter,
+                ..other()
+            });
+        }
+        let Some(rest) = name.strip_prefix("battery") else {
+            return Ok(other());
+        };
+
+        let (rest, charging, charged) = match (
+            rest.strip_suffix("-charging"),
+            rest.strip_suffix("-charged"),
+        ) {
+            (Some(rest), _) => (rest, true, false),
+            (None, Some(rest)) => (rest, false, true),
+            (None, None) => (rest, false, false),
+        };
+
+        let level = match rest.strip_prefix('-') {
+            None if rest.is_empty() => IconLevel::None,
+            None => return Ok(other()),
+            Some(level) => match level.strip_prefix("level-") {
+                Some(n) => match n.parse::<u8>() {
+                    Ok(n) if n <= 100 => IconLevel::Level(n),
+                    _ => return Ok(other()),
+                },
+                None => match IconLevel::NAMES.iter().find(|(n, _)| *n == level) {
+                    Some((_, level)) => *level,
+                    None => return Ok(other()),
+                },
+            },
+        };
+
+        Ok(Self {
+            kind: IconKind::Battery,
+            level,
+            charging,
+            charged,
+            symbolic,
+        })
+    }
+}
+impl ::core::fmt::Display for IconName {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        match &self.kind {
+            IconKind::Battery => f.write_str("battery")?,
+            IconKind::AcAdapter => f.write_str("ac-adapter")?,
+            IconKind::Other(name) => f.write_str(name)?,
+        }
+        if self.kind == IconKind::Battery {
+            match self.level {
+                IconLevel::Level(n) => write!(f, "-level-{n}")?,
+                IconLevel::None => {}
+                level => {
+                    if let Some((name, _)) = IconLevel::NAMES.iter().find(|(_, l)| *l == level) {
+                        write!(f, "-{name}")?;
+                    }
+                }
+            }
+            match (self.charging, self.charged) {
+                (true, _) => f.write_str("-charging")?,
+                (false, true) => f.write_str("-charged")?,
+                (false, false) => {}
+            }
+        }
+        if self.symbolic {
+            f.write_str("-symbolic")?;
+        }
+        Ok(())
+    }
+}
+
+impl BatteryState {
+    /// The character that marks this state under a chart
+    pub const fn chart_marker(self) -> char {
+        match self {
+            Self::Charging => '+',
+            Self::Discharging => '-',
+            Self::FullyCharged => '=',
+            Self::Empty => '!',
+            Self::PendingCharge | Self::PendingDischarge => '.',
+            Self::Unknown => ' ',
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trips<T: UPowerName + PartialEq + ::core::fmt::Debug>() {
+        for value in T::iter() {
+            let name = value.upower_name();
+            assert_eq!(T::from_upower_name(name).as_ref(), Some(&value), "{name}");
+            assert_eq!(
+                T::from_upower_name(&name.to_ascii_uppercase()).as_ref(),
+                Some(&value),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn upower_names_round_trip() {
+        round_trips::<BatteryState>();
+        round_trips::<WarningLevel>();
+        round_trips::<BatteryLevel>();
+        round_trips::<Technology>();
+        round_trips::<CapacityLevel>();
+        round_trips::<DeviceType>();
+        round_trips::<CriticalAction>();
+        assert_eq!(DeviceType::from_upower_name("linepower"), None);
+    }
+
+    #[test]
+    fn upower_names_match_upowerd() {
+        assert_eq!(DeviceType::LinePower.upower_name(), "line-power");
+        assert_eq!(DeviceType::GamingInput.upower_name(), "gaming-input");
+        assert_eq!(
+            DeviceType::BluetoothGeneric.upower_name(),
+            "bluetooth-generic"
+        );
+        assert_eq!(BatteryState::FullyCharged.upower_name(), "fully-charged");
+        assert_eq!(Technology::LithiumIon.upower_name(), "lithium-ion");
+        assert_eq!(CriticalAction::HybridSleep.upower_name(), "HybridSleep");
+    }
+
+    #[test]
+    fn percentages() {
+        assert_eq!(Percentage::new(0).map(|p| p.get()), Some(0));
+        assert_eq!(Percentage::new(100), Some(Percentage::MAX));
+        assert_eq!(Percentage::new(101), None);
+        assert_eq!(Percentage::new(u8::MAX), None);
+        assert_eq!(Percentage::new_saturating(0).get(), 0);
+        assert_eq!(Percentage::new_saturating(101), Percentage::MAX);
+
+        let from_f64 = |f: f64| Percentage::from_f64(f).map(|p| p.get());
+        assert_eq!(from_f64(0.0), Some(0));
+        assert_eq!(from_f64(-0.4), Some(0));
+        assert_eq!(from_f64(54.49), Some(54));
+        assert_eq!(from_f64(54.5), Some(55));
+        assert_eq!(from_f64(100.4), Some(100));
+        assert_eq!(from_f64(100.5), None);
+        assert_eq!(from_f64(-1.0), None);
+        assert_eq!(from_f64(f64::NAN), None);
+        assert_eq!(from_f64(f64::INFINITY), None);
+
+        let from_value = |v: Value<'static>| Percentage::try_from(OwnedValue::try_from(v).unwrap());
+        assert_eq!(from_value(Value::F64(99.6)), Ok(Percentage::MAX));
+        assert_eq!(from_value(Value::U32(42)).map(|p| p.get()), Ok(42));
+        assert_eq!(
+            from_value(Value::U8(101)),
+            Err(::zbus::zvariant::Error::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn level_indices() {
+        // (count, [0%, 9%, 10%, 99%, 100%])
+        let table: [(usize, [usize; 5]); 6] = [
+            (1, [0, 0, 0, 0, 0]),
+            (2, [0, 0, 0, 1, 1]),
+            (5, [0, 0, 0, 4, 4]),
+            (6, [0, 0, 0, 5, 5]),
+            (10, [0, 0, 1, 9, 9]),
+            (11, [0, 0, 1, 10, 10]),
+        ];
+        for (count, expected) in table {
+            for (percentage, index) in [0, 9, 10, 99, 100].into_iter().zip(expected) {
+                assert_eq!(
+                    IconSet::level_index(Percentage::new_saturating(percentage), count),
+                    Some(index),
+                    "{percentage}% of {count}"
+                );
+            }
+        }
+        assert_eq!(IconSet::level_index(Percentage::MAX, 0), None);
+    }
+
+    fn test_icons() -> IconSet {
+        IconSet {
+            charging: vec!["c0".to_owned(), "c1".to_owned()],
+            discharging: vec!["d0".to_owned(), "d1".to_owned(), "d2".to_owned()],
+            full: "full".to_owned(),
+            empty: "empty".to_owned(),
+            unknown: "unknown".to_owned(),
+            not_present: "not-present".to_owned(),
+            critical: "critical".to_owned(),
+        }
+    }
+
+    #[test]
+    fn icon_choices() {
+        let icons = test_icons();
+        let icon = |percentage: u8, state, warning_level| {
+            icons.icon(Percentage::new_saturating(percentage), state, warning_level)
+        };
+        use {BatteryState as S, WarningLevel as W};
+
+        assert_eq!(icon(50, S::Unknown, W::Critical), "unknown");
+        assert_eq!(icon(100, S::FullyCharged, W::None), "full");
+        assert_eq!(icon(50, S::FullyCharged, W::Critical), "full");
+        assert_eq!(icon(0, S::Empty, W::Action), "empty");
+
+        assert_eq!(icon(50, S::Discharging, W::Critical), "critical");
+        assert_eq!(icon(50, S::PendingDischarge, W::Action), "critical");
+        // Charging takes priority over a stale critical warning
+        assert_eq!(icon(5, S::Charging, W::Critical), "c0");
+        assert_eq!(icon(100, S::PendingCharge, W::Action), "c1");
+
+        assert_eq!(icon(0, S::Discharging, W::Low), "d0");
+        assert_eq!(icon(50, S::Discharging, W::None), "d1");
+        assert_eq!(icon(100, S::PendingDischarge, W::None), "d2");
+
+        let no_levels = IconSet {
+            discharging: Vec::new(),
+            ..test_icons()
+        };
+        assert_eq!(
+            no_levels.icon(Percentage::MAX, S::Discharging, W::None),
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn icons_for_devices() {
+        let icons = test_icons();
+        let details = DisplayDeviceDetails {
+            percentage: Percentage::new_saturating(80),
+            state: BatteryState::Discharging,
+            is_present: true,
+            ..Default::default()
+        };
+        assert_eq!(icons.icon_for(&details), "d2");
+        assert_eq!(
+            icons.icon_for(&DisplayDeviceDetails {
+                is_present: false,
+                warning_level: WarningLevel::Critical,
+                ..details
+            }),
+            "not-present"
+        );
+
+        let ascii = IconSet::ascii();
+        assert_eq!(
+            ascii.icon(
+                Percentage::new_saturating(0),
+                BatteryState::Discharging,
+                WarningLevel::None
+            ),
+            "[     ]"
+        );
+        assert_eq!(
+            ascii.icon(
+                Percentage::MAX,
+                BatteryState::Discharging,
+                WarningLevel::None
+            ),
+            "[|||||]"
+        );
+    }
+
+    #[test]
+    fn icon_names_round_trip() {
+        let battery = |level, charging, charged, symbolic| IconName {
+            kind: IconKind::Battery,
+            level,
+            charging,
+            charged,
+            symbolic,
+        };
+        for (name, expected) in [
+            (
+                "battery-level-50-charging-symbolic",
+                battery(IconLevel::Level(50), true, false, true),
+            ),
+            (
+                "battery-full-charged-symbolic",
+                battery(IconLevel::Full, false, true, true),
+            ),
+            (
+                "battery-missing",
+                battery(IconLevel::Missing, false, false, false),
+            ),
+            (
+                "ac-adapter-symbolic",
+                IconName {
+                    kind: IconKind::AcAdapter,
+                    ..battery(IconLevel::None, false, false, true)
+                },
+            ),
+            (
+                "input-mouse-symbolic",
+                IconName {
+                    kind: IconKind::Other("input-mouse".to_owned()),
+                    ..battery(IconLevel::None, false, false, true)
+                },
+            ),
+            // Not a level we know, so it's kept as it was
+            (
+                "battery-level-150",
+                IconName {
+                    kind: IconKind::Other("battery-level-150".to_owned()),
+                    ..battery(IconLevel::None, false, false, false)
+                },
+            ),
+        ] {
+            let parsed = name.parse::<IconName>().unwrap();
+            assert_eq!(parsed, expected, "{name}");
+            assert_eq!(parsed.to_string(), name);
+        }
+    }
+
+    #[test]
+    fn display_and_from_str_are_unchanged() {
+        assert_eq!(DeviceType::LinePower.to_string(), "LinePower");
+        assert_eq!("LinePower".parse::<DeviceType>(), Ok(DeviceType::LinePower));
+        assert!("line-power".parse::<DeviceType>().is_err());
+
+        assert_eq!(CriticalAction::PowerOff.to_string(), "PowerOff");
+        assert_eq!(
+            "PowerOff".parse::<CriticalAction>(),
+            Ok(CriticalAction::PowerOff)
+        );
+        assert!("poweroff".parse::<CriticalAction>().is_err());
+
+        assert_eq!(BatteryState::PendingCharge.to_string(), "pending-charge");
+        assert_eq!(
+            "pending-charge".parse::<BatteryState>(),
+            Ok(BatteryState::PendingCharge)
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Edge-triggered events for the display device's [`WarningLevel`]
+//!
+//! UPower only gives us the raw property, which will happily flip between `None` and `Low` every few seconds
+//! when the battery is sitting right on the threshold. This only reports real escalations and de-escalations.
+use {
+    crate::{
+        logging::*,
+        types::{BatteryState, Percentage, WarningLevel},
+        xmlgen::{display_device::DeviceProxy, DisplayDeviceDetails},
+    },
+    ::futures_util::{future::ready, Stream, StreamExt},
+    ::serde::{Deserialize, Serialize},
+};
+
+/// How reluctant the [`WarningTracker`] is to go back down a warning level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hysteresis {
+    /// How many percentage points the battery has to recover above the point where
+    /// the current warning level was entered before a de-escalation is reported.
+    pub percentage: u8,
+    /// If the battery starts charging, de-escalate right away instead of waiting for it to recover.
+    ///
+    /// This includes `PendingCharge`, which is what a battery with a charge limit reports while it sits on AC.
+    pub release_on_charge: bool,
+}
+impl Default for Hysteresis {
+    fn default() -> Self {
+        Self {
+            percentage: 2,
+            release_on_charge: true,
+        }
+    }
+}
+
+/// Which way the warning level moved
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    strum_macros::Display,
+    strum_macros::AsRefStr,
+    Serialize,
+    Deserialize,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningTransition {
+    Escalated,
+    Deescalated,
+}
+
+/// A single real change in warning level, along with the details that caused it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarningEvent {
+    pub transition: WarningTransition,
+    pub from: WarningLevel,
+    pub to: WarningLevel,
+    pub details: DisplayDeviceDetails,
+}
+
+/// The state machine behind [`watch`]. Feed it every update you get and it will tell you when something actually happened.
+#[derive(Debug, Clone)]
+pub struct WarningTracker {
+    hysteresis: Hysteresis,
+    level: WarningLevel,
+    /// The percentage the battery was at when we entered `level`
+    entered_at: Percentage,
+}
+impl WarningTracker {
+    pub const fn new(hysteresis: Hysteresis) -> Self {
+        Self {
+            hysteresis,
+            level: WarningLevel::None,
+            entered_at: Percentage::MAX,
+        }
+    }
+
+    /// The warning level that was last reported
+    #[inline]
+    pub const fn level(&self) -> WarningLevel {
+        self.level
+    }
+
+    /// Process a new set of details. Returns an event if the warning level really changed.
+    ///
+    /// `Unknown` warning levels are ignored.
+    pub fn update(&mut self, details: &DisplayDeviceDetails) -> Option<WarningEvent> {
+        let new_severity = details.warning_level.severity()?;
+        let current_severity = self.level.severity().unwrap_or_default();
+
+        let transition = if new_severity > current_severity {
+            WarningTransition::Escalated
+        } else if new_severity < current_severity && self.may_release(details) {
+            WarningTransition::Deescalated
+        } else {
+            return None;
+        };
+
+        let from = self.level;
+        self.level = details.warning_level;
+        self.entered_at = details.percentage;
+
+        debug!("Warning level {transition}: {from} -> {}", self.level);
+
+        Some(WarningEvent {
+            transition,
+            from,
+            to: self.level,
+            details: details.clone(),
+        })
+    }
+
+    fn may_release(&self, details: &DisplayDeviceDetails) -> bool {
+        if self.hysteresis.release_on_charge
+            && matches!(
+                details.state,
+                BatteryState::Charging | BatteryState::PendingCharge | BatteryState::FullyCharged
+            )
+        {
+            return true;
+        }
+
+        details.percentage.get()
+            >= self
+                .entered_at
+                .get()
+                .saturating_add(self.hysteresis.percentage)
+    }
+}
+impl Default for WarningTracker {
+    fn default() -> Self {
+        Self::new(Hysteresis::default())
+    }
+}
+
+/// Watch a device for warning level transitions.
+///
+/// If the battery is already low when this starts, the first event will be an escalation from `None`.
+pub async fn watch<'a>(
+    proxy: &'a DeviceProxy<'a>,
+    hysteresis: Hysteresis,
+) -> impl Stream<Item = WarningEvent> + 'a {
+    let mut tracker = WarningTracker::new(hysteresis);
+
+    DisplayDeviceDetails::watch(proxy)
+        .await
+        .filter_map(move |details| ready(tracker.update(&details)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(
+        percentage: u8,
+        state: BatteryState,
+        warning_level: WarningLevel,
+    ) -> DisplayDeviceDetails {
+        DisplayDeviceDetails {
+            percentage: Percentage::new_saturating(percentage),
+            state,
+            warning_level,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn holds_the_level_until_the_battery_recovers() {
+        let mut tracker = WarningTracker::default();
+        let low = tracker.update(&details(10, BatteryState::Discharging, WarningLevel::Low));
+        assert_eq!(
+            low.map(|e| e.transition),
+            Some(WarningTransition::Escalated)
+        );
+
+        // Flapping right on the threshold is not a de-escalation
+        assert!(tracker
+            .update(&details(11, BatteryState::Discharging, WarningLevel::None))
+            .is_none());
+        assert_eq!(tracker.level(), WarningLevel::Low);
+
+        let released = tracker.update(&details(12, BatteryState::Discharging, WarningLevel::None));
+        assert_eq!(
+            released.map(|e| e.transition),
+            Some(WarningTransition::Deescalated)
+        );
+    }
+
+    #[test]
+    fn releases_right_away_on_ac() {
+        for state in [
+            BatteryState::Charging,
+            BatteryState::PendingCharge,
+            BatteryState::FullyCharged,
+        ] {
+            let mut tracker = WarningTracker::default();
+            tracker.update(&details(10, BatteryState::Discharging, WarningLevel::Low));
+            let released = tracker.update(&details(10, state, WarningLevel::None));
+            assert_eq!(
+                released.map(|e| e.transition),
+                Some(WarningTransition::Deescalated),
+                "{state}"
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_unknown() {
+        let mut tracker = WarningTracker::default();
+        tracker.update(&details(10, BatteryState::Discharging, WarningLevel::Low));
+        assert!(tracker
+            .update(&details(10, BatteryState::Unknown, WarningLevel::Unknown))
+            .is_none());
+        assert_eq!(tracker.level(), WarningLevel::Low);
+    }
+}
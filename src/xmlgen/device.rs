@@ -0,0 +1,190 @@
+use zbus::proxy;
+
+use crate::types::{
+    BatteryLevel, BatteryState, CapacityLevel, DeviceType, IntSeconds, Percentage, Technology,
+    WarningLevel,
+};
+
+/// # D-Bus interface proxy for: `org.freedesktop.UPower.Device`
+///
+/// This code was generated by `zbus-xmlgen` `4.1.0` from D-Bus introspection data.
+/// Source: `Interface '/org/freedesktop/UPower/devices/battery_BAT0' from service 'org.freedesktop.UPower' on system bus`.
+///
+/// Unlike [`DeviceProxy`](crate::display_device::DeviceProxy), this has all the properties of a real device,
+/// so it has no default path. Get one from [`UPowerProxy::enumerate_devices`](crate::upower::UPowerProxy::enumerate_devices).
+///
+/// Not every property is filled in for every kind of device. https://upower.freedesktop.org/docs/Device.html
+#[proxy(
+    interface = "org.freedesktop.UPower.Device",
+    default_service = "org.freedesktop.UPower",
+    gen_blocking = false
+)]
+pub trait PowerDevice {
+    /// Refreshes the data collected from the power source.
+    fn refresh(&self) -> zbus::Result<()>;
+
+    /// Limit the battery charge to the configured start and end thresholds. Only available in UPower 1.90+.
+    fn enable_charge_threshold(&self, charge_threshold: bool) -> zbus::Result<()>;
+
+    /// Gets history for the power device that is persistent across reboots.
+    ///
+    /// `type_` is `rate`, `charge`, `time-full` or `time-empty`. `timespan` is how far back to go in seconds (0 for everything),
+    /// and `resolution` is roughly how many points to return. Each point is `(time, value, state)`, newest first.
+    fn get_history(
+        &self,
+        type_: &str,
+        timespan: u32,
+        resolution: u32,
+    ) -> zbus::Result<Vec<(u32, f64, BatteryState)>>;
+
+    /// OS specific native path of the power source. On Linux this is the sysfs path, for example `/sys/devices/LNXSYSTM:00/device:00/PNP0C0A:00/power_supply/BAT0`.
+    #[zbus(property)]
+    fn native_path(&self) -> zbus::Result<String>;
+
+    /// Name of the vendor of the battery.
+    #[zbus(property)]
+    fn vendor(&self) -> zbus::Result<String>;
+
+    /// Name of the model of this battery.
+    #[zbus(property)]
+    fn model(&self) -> zbus::Result<String>;
+
+    /// Unique serial number of the battery.
+    #[zbus(property)]
+    fn serial(&self) -> zbus::Result<String>;
+
+    /// The point in time (seconds since the Epoch) that data was read from the power source.
+    #[zbus(property)]
+    fn update_time(&self) -> zbus::Result<u64>;
+
+    /// Type of power source.
+    #[zbus(property)]
+    fn type_(&self) -> zbus::Result<DeviceType>;
+
+    /// If the power device is used to supply the system. This would be set TRUE for laptop batteries and UPS devices,
+    /// but set FALSE for wireless mice or PDAs.
+    #[zbus(property)]
+    fn power_supply(&self) -> zbus::Result<bool>;
+
+    /// If the power device has history.
+    #[zbus(property)]
+    fn has_history(&self) -> zbus::Result<bool>;
+
+    /// If the power device has statistics.
+    #[zbus(property)]
+    fn has_statistics(&self) -> zbus::Result<bool>;
+
+    /// Whether power is currently being provided through line power. Only valid for line power devices.
+    #[zbus(property)]
+    fn online(&self) -> zbus::Result<bool>;
+
+    /// Amount of energy (measured in Wh) currently available in the power source.
+    #[zbus(property)]
+    fn energy(&self) -> zbus::Result<f64>;
+
+    /// Amount of energy (measured in Wh) in the power source when it's considered to be empty.
+    #[zbus(property)]
+    fn energy_empty(&self) -> zbus::Result<f64>;
+
+    /// Amount of energy (measured in Wh) in the power source when it's considered full.
+    #[zbus(property)]
+    fn energy_full(&self) -> zbus::Result<f64>;
+
+    /// Amount of energy (measured in Wh) the power source is designed to hold when it's considered full.
+    #[zbus(property)]
+    fn energy_full_design(&self) -> zbus::Result<f64>;
+
+    /// Discharging/charging rate of the source, measured in Watt.
+    #[zbus(property)]
+    fn energy_rate(&self) -> zbus::Result<f64>;
+
+    /// Voltage in the Cell or being recorded by the meter.
+    #[zbus(property)]
+    fn voltage(&self) -> zbus::Result<f64>;
+
+    /// The number of charge cycles as defined by the TCO certification, or -1 if that value is unknown or not applicable.
+    #[zbus(property)]
+    fn charge_cycles(&self) -> zbus::Result<i32>;
+
+    /// Luminosity being recorded by the meter.
+    #[zbus(property)]
+    fn luminosity(&self) -> zbus::Result<f64>;
+
+    /// Number of seconds until the power source is considered empty. Is set to 0 if unknown.
+    #[zbus(property)]
+    fn time_to_empty(&self) -> zbus::Result<IntSeconds>;
+
+    /// Number of seconds until the power source is considered full. Is set to 0 if unknown.
+    #[zbus(property)]
+    fn time_to_full(&self) -> zbus::Result<IntSeconds>;
+
+    /// The amount of energy left in the power source expressed as a percentage between 0 and 100.
+    #[zbus(property)]
+    fn percentage(&self) -> zbus::Result<Percentage>;
+
+    /// The temperature of the device in degrees Celsius.
+    #[zbus(property)]
+    fn temperature(&self) -> zbus::Result<f64>;
+
+    /// If the power source is present in the bay.
+    #[zbus(property)]
+    fn is_present(&self) -> zbus::Result<bool>;
+
+    /// The battery power state.
+    #[zbus(property)]
+    fn state(&self) -> zbus::Result<BatteryState>;
+
+    /// If the power source is rechargeable.
+    #[zbus(property)]
+    fn is_rechargeable(&self) -> zbus::Result<bool>;
+
+    /// The capacity of the power source expressed as a percentage between 0 and 100.
+    /// The capacity of the battery will reduce with age.
+    #[zbus(property)]
+    fn capacity(&self) -> zbus::Result<f64>;
+
+    /// Technology used in the battery.
+    #[zbus(property)]
+    fn technology(&self) -> zbus::Result<Technology>;
+
+    /// Warning level of the battery.
+    #[zbus(property)]
+    fn warning_level(&self) -> zbus::Result<WarningLevel>;
+
+    /// The level of the battery for devices which do not report a percentage but rather a coarse battery level.
+    /// If the value is None, then the device does not support coarse battery reporting, and the percentage should be used instead.
+    #[zbus(property)]
+    fn battery_level(&self) -> zbus::Result<BatteryLevel>;
+
+    /// An icon name, following the Icon Naming Specification.
+    #[zbus(property)]
+    fn icon_name(&self) -> zbus::Result<String>;
+
+    /// The minimum design voltage of the battery, as reported by the kernel.
+    #[zbus(property)]
+    fn voltage_min_design(&self) -> zbus::Result<f64>;
+
+    /// The maximum design voltage of the battery, as reported by the kernel.
+    #[zbus(property)]
+    fn voltage_max_design(&self) -> zbus::Result<f64>;
+
+    /// When a start charge threshold is set the battery won't get charged until the charge drops under this threshold.
+    #[zbus(property)]
+    fn charge_start_threshold(&self) -> zbus::Result<Percentage>;
+
+    /// The end charge threshold stops the battery from getting charged after the set threshold.
+    #[zbus(property)]
+    fn charge_end_threshold(&self) -> zbus::Result<Percentage>;
+
+    /// If battery charge start and end limits are applied.
+    #[zbus(property)]
+    fn charge_threshold_enabled(&self) -> zbus::Result<bool>;
+
+    /// If setting battery charge limits is supported.
+    #[zbus(property)]
+    fn charge_threshold_supported(&self) -> zbus::Result<bool>;
+
+    /// Coarse representation of battery capacity. Only available in UPower 1.90+.
+    #[zbus(property)]
+    fn capacity_level(&self) -> zbus::Result<CapacityLevel>;
+}
@@ -1,22 +1,36 @@
-use crate::types::{BatteryState, DeviceType, IntSeconds, Percentage, WarningLevel};
+use {
+    crate::{
+        logging::*,
+        types::{
+            BatteryLevel, BatteryState, DeviceType, IntSeconds, Percentage, Technology,
+            WarningLevel,
+        },
+    },
+    ::futures_util::{future::ready, stream, Stream, StreamExt},
+};
 
+pub mod device;
 pub mod display_device;
 pub mod keyboard;
 pub mod upower;
 
-macro_rules! disp_device_details {
-    ($( $property:ident: $type:ty ),+$(,)?) => {
-        /// All the details that can be provided by [`display_device`]
-        #[derive(Debug, Default, Clone)]
-        pub struct DisplayDeviceDetails {
+macro_rules! device_details {
+    (
+        $(#[$meta:meta])*
+        $details:ident, $result:ident => $($proxy:ident)::+;
+        $( $property:ident: $type:ty ),+$(,)?
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Default, Clone, ::serde::Serialize, ::serde::Deserialize)]
+        pub struct $details {
             $( pub $property: $type, )+
         }
         #[derive(Debug, Clone)]
-        pub struct DisplayDeviceDetailsResult {
+        pub struct $result {
             $( pub $property: ::zbus::Result<$type>, )+
         }
 
-        impl Default for DisplayDeviceDetailsResult {
+        impl Default for $result {
             fn default() -> Self {
                 Self {
                     $( $property: Err(::zbus::Error::Variant(::zbus::zvariant::Error::IncorrectType)) ),+
@@ -24,10 +38,10 @@ macro_rules! disp_device_details {
             }
         }
 
-        impl DisplayDeviceDetailsResult {
+        impl $result {
             /// Try to extract the data payload, stopping at the first error.
-            pub fn try_resolve(self) -> ::zbus::Result<DisplayDeviceDetails> {
-                let mut me = DisplayDeviceDetails::default();
+            pub fn try_resolve(self) -> ::zbus::Result<$details> {
+                let mut me = $details::default();
 
                 $(
                     match self.$property {
@@ -41,10 +55,10 @@ macro_rules! disp_device_details {
         }
 
 
-        impl DisplayDeviceDetails {
+        impl $details {
             /// simply request all the properties.
-            pub async fn request_all<'c>(proxy: &$crate::xmlgen::display_device::DeviceProxy<'c>) -> DisplayDeviceDetailsResult {
-                let mut me = DisplayDeviceDetailsResult::default();
+            pub async fn request_all<'c>(proxy: &$($proxy)::+<'c>) -> $result {
+                let mut me = $result::default();
                 let ($( $property ),+) = ::futures_util::join!( $(proxy.$property()),+ );
                 $(
                     me.$property = $property;
@@ -56,7 +70,9 @@ macro_rules! disp_device_details {
     };
 }
 
-disp_device_details! {
+device_details! {
+    /// All the details that can be provided by [`display_device`]
+    DisplayDeviceDetails, DisplayDeviceDetailsResult => display_device::DeviceProxy;
     energy: f64,
     energy_full: f64,
     energy_rate: f64,
@@ -69,3 +85,95 @@ disp_device_details! {
     type_: DeviceType,
     warning_level: WarningLevel,
 }
+
+device_details! {
+    /// A snapshot of a real device from [`device`]. This has everything [`DisplayDeviceDetails`] has, and more.
+    ///
+    /// Properties that only exist in very recent versions of UPower are left out, so this works with older daemons too.
+    DeviceDetails, DeviceDetailsResult => device::PowerDeviceProxy;
+    native_path: String,
+    vendor: String,
+    model: String,
+    serial: String,
+    update_time: u64,
+    type_: DeviceType,
+    power_supply: bool,
+    online: bool,
+    energy: f64,
+    energy_empty: f64,
+    energy_full: f64,
+    energy_full_design: f64,
+    energy_rate: f64,
+    voltage: f64,
+    charge_cycles: i32,
+    luminosity: f64,
+    time_to_empty: IntSeconds,
+    time_to_full: IntSeconds,
+    percentage: Percentage,
+    temperature: f64,
+    is_present: bool,
+    state: BatteryState,
+    is_rechargeable: bool,
+    capacity: f64,
+    technology: Technology,
+    warning_level: WarningLevel,
+    battery_level: BatteryLevel,
+    icon_name: String,
+}
+impl From<&DeviceDetails> for DisplayDeviceDetails {
+    fn from(value: &DeviceDetails) -> Self {
+        Self {
+            energy: value.energy,
+            energy_full: value.energy_full,
+            energy_rate: value.energy_rate,
+            icon_name: value.icon_name.clone(),
+            is_present: value.is_present,
+            percentage: value.percentage,
+            state: value.state,
+            time_to_empty: value.time_to_empty,
+            time_to_full: value.time_to_full,
+            type_: value.type_,
+            warning_level: value.warning_level,
+        }
+    }
+}
+
+impl DisplayDeviceDetails {
+    /// Get a fresh copy of all the details right away, and then again every time one of the
+    /// properties that matter for notifications and thresholds changes (state, percentage, time estimates,
+    /// warning level, energy or energy rate).
+    ///
+    /// Failed requests are logged and skipped.
+    pub async fn watch<'a>(
+        proxy: &'a display_device::DeviceProxy<'a>,
+    ) -> impl Stream<Item = DisplayDeviceDetails> + 'a {
+        let state = proxy.receive_state_changed().await.map(|_| ());
+        let percentage = proxy.receive_percentage_changed().await.map(|_| ());
+        let warning_level = proxy.receive_warning_level_changed().await.map(|_| ());
+        let time_to_empty = proxy.receive_time_to_empty_changed().await.map(|_| ());
+        let time_to_full = proxy.receive_time_to_full_changed().await.map(|_| ());
+        let energy = proxy.receive_energy_changed().await.map(|_| ());
+        let energy_rate = proxy.receive_energy_rate_changed().await.map(|_| ());
+
+        let changes = stream::select(
+            stream::select(
+                stream::select(state, percentage),
+                stream::select(energy, energy_rate),
+            ),
+            stream::select(warning_level, stream::select(time_to_empty, time_to_full)),
+        );
+
+        stream::once(ready(()))
+            .chain(changes)
+            .then(move |_| Self::request_all(proxy))
+            .filter_map(|result| {
+                ready(match result.try_resolve() {
+                    Ok(details) => Some(details),
+                    Err(e) => {
+                        warning!("Failed to get device details: {}", e);
+                        None
+                    }
+                })
+            })
+    }
+}